@@ -0,0 +1,250 @@
+// a unix socket that other programs (and `new_music_player ctl`) can send commands to.
+//
+// the protocol is one command per line. every command gets back zero or more lines of output and then
+// a line that is either `ok` or `error <why>`. the commands are:
+//   play | pause | toggle | next | prev | quit
+//   seek <seconds>      seek to a point in the song. `seek +10` / `seek -10` seeks relative to where we are
+//   volume <0-100>      set the volume
+//   enqueue <path>      add a song, folder or playlist to the end of the queue (path should be absolute)
+//   playnext <path>     same as enqueue but puts them right after the current song
+//   queue               list the upcoming songs, one per line
+//   save <path>         save the current song and the queue to a M3U8 playlist (path should be absolute)
+//   mute <channel>      mute or unmute a channel (from 1) of the tracker module that is playing
+//   solo <channel>      play only that channel, or all of them again if it allready was the only one
+//   unmute              unmute every channel
+//   status              print what is playing as `key: value` lines (and where a tracker module is up to)
+
+use std::{io::{self, BufRead, BufReader, Write}, os::unix::net::{UnixListener, UnixStream}, path::{Path, PathBuf}, sync::OnceLock, thread};
+
+use souvlaki::{MediaControlEvent, MediaPosition};
+use std::time::Duration;
+
+use crate::{dirs::runtime_file, get_songs, group, update_playback, Status, GLOBAL_STATE};
+
+/// the path of the control socket
+pub fn socket_path() -> PathBuf {
+    runtime_file("sock")
+}
+
+/// the socket this player made, so we only clean up our own and not the one of a player that was already running
+static BOUND: OnceLock<PathBuf> = OnceLock::new();
+
+/// starts listening for commands on the control socket (on its own thread)
+pub fn listen() {
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        println!("another player is allready listening on {path:?}. not starting the control socket");
+        return;
+    }
+    let _ = std::fs::remove_file(&path); // left over from a player that did not exit cleanly
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("could not start the control socket at {path:?}: {e}");
+            return;
+        }
+    };
+    let _ = BOUND.set(path);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || handle_client(stream)); // one thread per client so a slow one does not block the others
+        }
+    });
+}
+
+/// removes the control socket file if we made it (call on exit)
+pub fn cleanup() {
+    if let Some(path) = BOUND.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// answers commands from one client until it hangs up
+fn handle_client(stream: UnixStream) {
+    let Ok(mut writer) = stream.try_clone() else { return };
+    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = {
+            let mut state = GLOBAL_STATE.get().unwrap().lock().unwrap(); //lock the state so we can change it
+            let reply = run_command(&mut state, line.trim());
+            update_playback(&mut state);
+            reply
+        };
+        let text = match reply {
+            Ok(lines) => lines.into_iter().map(|l| l + "\n").collect::<String>() + "ok\n",
+            Err(e) => format!("error {e}\n"),
+        };
+        if writer.write_all(text.as_bytes()).is_err() {
+            return;
+        }
+        if line.split_whitespace().next() == Some("quit") {
+            GLOBAL_STATE.get().unwrap().lock().unwrap().handle_event(MediaControlEvent::Quit);
+        }
+    }
+}
+
+/// runs a single command against the player, returning the lines to send back
+fn run_command(state: &mut Status, line: &str) -> Result<Vec<String>, String> {
+    let (command, arg) = line.split_once(' ').map_or((line, ""), |(c, a)| (c, a.trim()));
+    match command {
+        "play" => state.handle_event(MediaControlEvent::Play),
+        "pause" => state.handle_event(MediaControlEvent::Pause),
+        "toggle" => state.handle_event(MediaControlEvent::Toggle),
+        "next" => state.handle_event(MediaControlEvent::Next),
+        "prev" | "previous" => state.handle_event(MediaControlEvent::Previous),
+        "quit" => {} // exits the player, so `handle_client` does it once it has answered
+        "seek" => {
+            // `inf` and `NaN` parse too, and would panic in `Duration` (with the state locked, which takes the player down)
+            let seconds: f64 = arg.parse().ok().filter(|x: &f64| x.is_finite()).ok_or_else(|| format!("bad number of seconds '{arg}'"))?;
+            let amount = Duration::try_from_secs_f64(seconds.abs()).map_err(|_| format!("can not seek by {arg} seconds"))?;
+            if arg.starts_with(['+', '-']) {
+                state.seek_by(seconds);
+            } else {
+                state.handle_event(MediaControlEvent::SetPosition(MediaPosition(amount)));
+            }
+        }
+        "volume" => {
+            let percent: f64 = arg.parse().ok().filter(|x: &f64| x.is_finite()).ok_or_else(|| format!("bad volume '{arg}'"))?;
+            state.set_volume(percent / 100.0);
+        }
+        "enqueue" => {
+            if arg.is_empty() {
+                return Err("enqueue needs a path".to_string());
+            }
+            let songs = get_songs(Path::new(arg));
+            let count = songs.len();
+            state.upcoming.extend(songs.into_iter().rev()); // get_songs hands them back reversed
+            return Ok(vec![format!("added {count} songs")]);
+        }
+        "playnext" => {
+            if arg.is_empty() {
+                return Err("playnext needs a path".to_string());
+            }
+            state.cancel_preload(); // so the new songs go before the one we lined up
+            let songs = get_songs(Path::new(arg));
+            let count = songs.len();
+            for song in songs { // allready reversed, so pushing each to the front puts them in order
+                state.upcoming.push_front(song);
+            }
+            return Ok(vec![format!("added {count} songs")]);
+        }
+        "queue" => {
+            return Ok(state.preloaded.iter().map(|p| &p.song).chain(state.upcoming.iter())
+                .map(|song| song.path.to_string_lossy().into_owned())
+                .collect());
+        }
+        "save" => {
+            if arg.is_empty() {
+                return Err("save needs a path".to_string());
+            }
+            let count = state.save_queue(Path::new(arg)).map_err(|e| format!("could not write {arg:?}: {e}"))?;
+            return Ok(vec![format!("saved {count} songs")]);
+        }
+        "mute" | "solo" => {
+            let channel: usize = arg.parse().map_err(|_| format!("bad channel '{arg}'"))?;
+            state.toggle_channel(channel, command == "solo")?;
+        }
+        "unmute" => state.unmute_channels(),
+        "status" => return Ok(status_lines(state)),
+        x => return Err(format!("unknown command '{x}'")),
+    }
+    Ok(vec![])
+}
+
+/// the lines `status` prints
+fn status_lines(state: &Status) -> Vec<String> {
+    let mut lines = vec![format!("state: {}", match (&state.handle, state.paused) {
+        (None, _) => "stopped",
+        (Some(_), true) => "paused",
+        (Some(_), false) => "playing",
+    })];
+    if let Some(song) = state.lookback.front() {
+        lines.push(format!("path: {}", song.path.to_string_lossy()));
+    }
+    for (key, value) in [("title", &state.tags.title), ("artist", &state.tags.artist), ("album", &state.tags.album)] {
+        if let Some(value) = value {
+            lines.push(format!("{key}: {value}"));
+        }
+    }
+    lines.push(format!("position: {:.1}", state.handle.as_ref().map_or(0.0, |h| h.position())));
+    lines.push(format!("duration: {:.1}", state.duration.as_secs_f64()));
+    lines.push(format!("volume: {:.0}", state.volume * 100.0));
+    lines.push(format!("queued: {}", state.upcoming.len() + state.preloaded.iter().count()));
+    if let Some(tracker) = &state.tracker {
+        let tracker = tracker.lock().unwrap();
+        lines.push(format!("order: {}/{}", tracker.order, tracker.num_orders));
+        lines.push(format!("pattern: {}", tracker.pattern));
+        lines.push(format!("row: {}", tracker.row));
+        lines.push(format!("speed: {}", tracker.speed));
+        lines.push(format!("tempo: {}", tracker.tempo));
+        // `channel: <number> <left vu> <right vu> [muted]`, one line each
+        for (i, (&(left, right), &muted)) in tracker.vu.iter().zip(tracker.muted.iter()).enumerate() {
+            lines.push(format!("channel: {} {left:.2} {right:.2}{}", i + 1, if muted { " muted" } else { "" }));
+        }
+    }
+    lines
+}
+
+/// hands `files` to a player that is already running, so we do not start a second one.
+/// returns false if there is no player to hand them to. `flags` are the ones we were given that the running player
+/// can not take, if there are any we give up with a error instead of quietly dropping them
+pub fn forward(files: &[PathBuf], next: bool, flags: &[String]) -> Result<bool, String> {
+    if UnixStream::connect(socket_path()).is_err() {
+        return Ok(false);
+    }
+    if !flags.is_empty() {
+        return Err(format!("a player is already running and can not be handed {}. use --new-instance to start another one", flags.join(", ")));
+    }
+    if files.is_empty() {
+        println!("a player is already running");
+    }
+    // `playnext` puts each one at the front, so send them backwards to keep them in order
+    let files: Vec<&PathBuf> = if next { files.iter().rev().collect() } else { files.iter().collect() };
+    for file in files {
+        let path = match absolute(file) {
+            Ok(path) => path,
+            Err(e) => {
+                println!("bad group {file:?}: {e}");
+                continue;
+            }
+        };
+        match send(&format!("{} {}", if next { "playnext" } else { "enqueue" }, path.to_string_lossy())) {
+            Ok(Ok(lines)) => println!("{}: {}", path.to_string_lossy(), lines.join(" ")),
+            Ok(Err(e)) => println!("{}: {e}", path.to_string_lossy()),
+            Err(e) => println!("could not hand {path:?} to the running player: {e}"),
+        }
+    }
+    Ok(true)
+}
+
+/// the player does not know what folder we are in, so paths (and the paths in `@` groups) are made absolute
+/// before they are sent
+pub fn absolute(file: &Path) -> Result<PathBuf, group::ParseError> {
+    let text = file.to_string_lossy();
+    if text.starts_with('@') {
+        Ok(PathBuf::from(group::absolute(&text)?))
+    } else {
+        Ok(std::path::absolute(file).unwrap_or(file.to_path_buf()))
+    }
+}
+
+/// sends one command to the running player and returns its output lines.
+/// a `error` reply comes back as a `Err` with the player's reason
+pub fn send(command: &str) -> io::Result<Result<Vec<String>, String>> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+    let mut lines = vec![];
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line == "ok" {
+            return Ok(Ok(lines));
+        }
+        if let Some(e) = line.strip_prefix("error ") {
+            return Ok(Err(e.to_string()));
+        }
+        lines.push(line);
+    }
+    Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the player hung up without answering"))
+}
@@ -0,0 +1,83 @@
+// finds cover art for a song and puts it somewhere souvlaki can point at with a `file://` url
+
+use std::{fs, path::{Path, PathBuf}, sync::Arc};
+
+use symphonia::core::meta::{StandardVisualKey, Visual};
+
+use crate::dirs::cache_dir;
+
+/// file names (without extension, any case) that count as cover art when they sit next to a song
+const FOLDER_NAMES: [&str; 5] = ["cover", "folder", "front", "album", "albumart"];
+/// image extensions we look for next to a song
+const FOLDER_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// a picture embedded in a song. only written out to the cache once the song plays (see `cache`), so opening a file
+/// just to read its tags (the library index, loudness measuring) does not fill the cache with every cover there is
+#[derive(Clone)]
+pub struct Picture {
+    /// the mime type, e.g. `image/png`
+    pub media_type: String,
+    /// the image file itself
+    pub data: Arc<[u8]>,
+}
+
+impl std::fmt::Debug for Picture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Picture({}, {} bytes)", self.media_type, self.data.len()) // not the whole image
+    }
+}
+
+/// picks the front cover out of a file's embedded pictures (or the first picture if none are marked as the front)
+pub fn embedded_cover(visuals: &[Visual]) -> Option<Picture> {
+    let visual = visuals.iter().find(|v| v.usage == Some(StandardVisualKey::FrontCover)).or(visuals.first())?;
+    Some(Picture { media_type: visual.media_type.clone(), data: visual.data.clone().into() })
+}
+
+/// writes a embedded picture to the cache (if it is not there allready). returns its url
+pub fn cache(picture: &Picture) -> Option<String> {
+    let ext = match picture.media_type.as_str() {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/bmp" => "bmp",
+        "image/webp" => "webp",
+        _ => "jpg",
+    };
+    // name the file after its contents so every song on a album shares one file
+    let dir = cache_dir().join("covers");
+    let path = dir.join(format!("{:016x}.{ext}", fnv1a(&picture.data)));
+    if !path.exists() {
+        fs::create_dir_all(&dir).ok()?;
+        fs::write(&path, &picture.data).ok()?;
+    }
+    Some(file_url(&path))
+}
+
+/// looks for a `cover.jpg`, `folder.png` or similar in the song's folder. returns its url
+pub fn folder_cover(song: &Path) -> Option<String> {
+    let entries = fs::read_dir(song.parent()?).ok()?;
+    entries.flatten().map(|entry| entry.path()).find(|path| {
+        let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("").to_lowercase();
+        let ext = path.extension().and_then(|x| x.to_str()).unwrap_or("").to_lowercase();
+        FOLDER_NAMES.contains(&stem.as_str()) && FOLDER_EXTENSIONS.contains(&ext.as_str())
+    }).map(|path| file_url(&path))
+}
+
+/// turns a path into a `file://` url (made absolute first, since urls have no working directory).
+/// anything that is not a plain letter/number/`/-._~` gets percent encoded so spaces etc do not break it
+fn file_url(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let mut url = String::from("file://");
+    for &b in path.to_string_lossy().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{b:02X}"));
+        }
+    }
+    url
+}
+
+/// a small fast hash (64 bit FNV-1a). good enough to tell images apart, and stable between runs unlike `DefaultHasher`
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
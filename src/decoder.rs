@@ -0,0 +1,193 @@
+// one decoder type for every song, so the playback code does not care if it is a mp3 or a tracker module.
+// which decoder a file gets is up to the formats in `format.rs`
+
+use std::{fmt, path::Path, sync::{Arc, Mutex}, time::Duration};
+
+use kira::{
+    dsp::Frame,
+    sound::{streaming::Decoder, FromFileError},
+};
+
+use crate::{format::{self, SongDecoder}, moddecoder::TrackerState, tags::Tags};
+
+/// why a song could not be opened for playback
+#[derive(Debug)]
+pub enum OpenError {
+    /// we do not know how to play files with this extension
+    Unsupported(String),
+    /// libopenmpt could not load the tracker module
+    Module,
+    /// symphonia (or the file system) could not open the file
+    File(FromFileError),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenError::Unsupported(ext) => write!(f, "unsupported format '{ext}'"),
+            OpenError::Module => write!(f, "libopenmpt could not load the module"),
+            OpenError::File(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// a part of a file to play instead of all of it (a track of a CUE sheet)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    /// where the part starts
+    pub start: Duration,
+    /// where it ends. `None` plays to the end of the file
+    pub end: Option<Duration>,
+}
+
+/// a streaming decoder for any song we know how to play
+pub enum TrackDecoder {
+    /// a whole file, decoded by whatever its format hands back
+    Whole(Box<dyn SongDecoder>),
+    /// only part of a file. looks like the part is the whole song to everything else
+    Region(Box<RegionDecoder>),
+}
+
+/// plays just a region of another decoder. frame 0 is the start of the region
+pub struct RegionDecoder {
+    inner: TrackDecoder,
+    /// the first frame of the region in the inner decoder
+    start: usize,
+    /// the frame after the last frame of the region in the inner decoder
+    end: usize,
+    /// the frame the inner decoder will decode next
+    position: usize,
+}
+
+impl RegionDecoder {
+    fn new(mut inner: TrackDecoder, region: Region) -> Result<RegionDecoder, FromFileError> {
+        let rate = inner.sample_rate() as f64;
+        let total = inner.num_frames();
+        let start = ((region.start.as_secs_f64() * rate) as usize).min(total);
+        let end = region.end.map_or(total, |end| (end.as_secs_f64() * rate) as usize).clamp(start, total);
+        let position = inner.seek(start)?;
+        Ok(RegionDecoder { inner, start, end, position })
+    }
+}
+
+impl Decoder for RegionDecoder {
+    type Error = FromFileError;
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn num_frames(&self) -> usize {
+        self.end - self.start
+    }
+
+    fn decode(&mut self) -> Result<Vec<Frame>, Self::Error> {
+        loop {
+            if self.position >= self.end {
+                return Ok(vec![Frame::ZERO; 1024]); // past the end of the region. kira stops on its own, see `FileDecoder::decode`
+            }
+            let mut frames = self.inner.decode()?;
+            let first = self.position;
+            self.position += frames.len();
+            // seeking can land a little before the start, so drop anything before it
+            frames.drain(..self.start.saturating_sub(first).min(frames.len()));
+            frames.truncate(self.end.saturating_sub(first.max(self.start)));
+            if !frames.is_empty() {
+                return Ok(frames);
+            }
+        }
+    }
+
+    fn seek(&mut self, index: usize) -> Result<usize, Self::Error> {
+        self.position = self.inner.seek(self.start + index.min(self.num_frames()))?;
+        Ok(self.position.saturating_sub(self.start))
+    }
+}
+
+impl TrackDecoder {
+    /// picks the right decoder for `path` (see `format.rs`) and opens it
+    pub fn open(path: &Path) -> Result<TrackDecoder, OpenError> {
+        TrackDecoder::open_subsong(path, None)
+    }
+
+    /// like `open`, but plays `subsong` if it is a tracker module
+    fn open_subsong(path: &Path, subsong: Option<i32>) -> Result<TrackDecoder, OpenError> {
+        match format::find(path) {
+            Some(format) => format.open(path, subsong).map(TrackDecoder::Whole),
+            None => Err(OpenError::Unsupported(path.extension().map_or(String::new(), |x| x.to_string_lossy().to_lowercase()))),
+        }
+    }
+
+    /// like `open` but only plays part of the file: `region` of it (if there is one), and `subsong` of a tracker module
+    pub fn open_part(path: &Path, region: Option<Region>, subsong: Option<i32>) -> Result<TrackDecoder, OpenError> {
+        let decoder = TrackDecoder::open_subsong(path, subsong)?;
+        match region {
+            Some(region) => Ok(TrackDecoder::Region(Box::new(RegionDecoder::new(decoder, region).map_err(OpenError::File)?))),
+            None => Ok(decoder),
+        }
+    }
+
+    /// how long the song naturally is. tracker modules are counted once through, however many times they are set to play
+    pub fn length(&self) -> Duration {
+        match self {
+            TrackDecoder::Whole(d) => d.length(),
+            TrackDecoder::Region(_) => Duration::from_secs_f64(self.num_frames() as f64 / self.sample_rate() as f64),
+        }
+    }
+
+    /// how long to fade out at the end of the song, for songs that do not end by themselves (tracker modules that loop)
+    pub fn fade_out(&self) -> Option<Duration> {
+        match self {
+            TrackDecoder::Whole(d) => d.fade_out(),
+            TrackDecoder::Region(d) => d.inner.fade_out(),
+        }
+    }
+
+    /// what the tracker module is up to as it plays (and which channels to mute). `None` if it is not one
+    pub fn tracker(&self) -> Option<Arc<Mutex<TrackerState>>> {
+        match self {
+            TrackDecoder::Whole(d) => d.tracker(),
+            TrackDecoder::Region(d) => d.inner.tracker(),
+        }
+    }
+
+    /// the tags of the song
+    pub fn tags(&self) -> Tags {
+        match self {
+            TrackDecoder::Whole(d) => d.tags(),
+            TrackDecoder::Region(d) => d.inner.tags(),
+        }
+    }
+}
+
+impl Decoder for TrackDecoder {
+    type Error = FromFileError;
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            TrackDecoder::Whole(d) => d.sample_rate(),
+            TrackDecoder::Region(d) => d.sample_rate(),
+        }
+    }
+
+    fn num_frames(&self) -> usize {
+        match self {
+            TrackDecoder::Whole(d) => d.num_frames(),
+            TrackDecoder::Region(d) => d.num_frames(),
+        }
+    }
+
+    fn decode(&mut self) -> Result<Vec<Frame>, Self::Error> {
+        match self {
+            TrackDecoder::Whole(d) => d.decode(),
+            TrackDecoder::Region(d) => d.decode(),
+        }
+    }
+
+    fn seek(&mut self, index: usize) -> Result<usize, Self::Error> {
+        match self {
+            TrackDecoder::Whole(d) => d.seek(index),
+            TrackDecoder::Region(d) => d.seek(index),
+        }
+    }
+}
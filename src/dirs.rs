@@ -0,0 +1,35 @@
+// where the player keeps its files. follows the XDG base directory spec (with the usual fallbacks under $HOME)
+
+use std::{env, path::PathBuf};
+
+/// the name of the folder we make inside the XDG directories
+const APP_DIR: &str = "new_music_player";
+
+/// reads a XDG directory variable, falling back to `$HOME/<fallback>` if it is not set (or not absolute like the spec says)
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(fallback))
+        .join(APP_DIR)
+}
+
+/// where we cache things that can be remade (cover art etc). `$XDG_CACHE_HOME/new_music_player`
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// where we keep things that should last between runs but are not worth backing up (the session etc).
+/// `$XDG_STATE_HOME/new_music_player`
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// where we put sockets and other things that only live as long as the player runs.
+/// `$XDG_RUNTIME_DIR`, or the temp folder if there is none (then the user name goes in the file names so users do not clash)
+pub fn runtime_file(name: &str) -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        Some(dir) => dir.join(format!("{APP_DIR}.{name}")),
+        None => env::temp_dir().join(format!("{APP_DIR}-{}.{name}", env::var("USER").unwrap_or_default())),
+    }
+}
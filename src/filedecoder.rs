@@ -0,0 +1,172 @@
+// streams regular audio files (wav/mp3/flac/ogg) through symphonia a packet at a time.
+// kira has one of these built in, but it is private so we cannot share it with the tracker decoder.
+
+use std::{fs::File, io::ErrorKind, path::Path};
+
+use kira::{
+    dsp::Frame,
+    sound::{streaming::Decoder, FromFileError},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder as SymphoniaDecoder, DecoderOptions},
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::{decoder::OpenError, format::{Format, SongDecoder}, tags::Tags};
+
+/// how many frames of silence we hand kira once the file has run out of packets
+const SILENCE_CHUNK: usize = 1024;
+
+/// the formats symphonia decodes for us (the ones kira's symphonia features turn on)
+pub struct FileFormat {
+    extensions: Vec<String>,
+}
+
+impl FileFormat {
+    pub fn new() -> FileFormat {
+        FileFormat { extensions: ["wav", "mp3", "flac", "ogg"].map(String::from).to_vec() }
+    }
+}
+
+impl Format for FileFormat {
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn probe(&self, header: &[u8], _file_size: u64) -> bool {
+        match header {
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => true,
+            [b'f', b'L', b'a', b'C', ..] | [b'O', b'g', b'g', b'S', ..] => true,
+            [b'I', b'D', b'3', ..] => true, // a mp3 with id3 tags in front
+            [0xff, second, ..] => second & 0xe0 == 0xe0, // a mp3 frame without tags
+            _ => false,
+        }
+    }
+
+    fn open(&self, path: &Path, _subsong: Option<i32>) -> Result<Box<dyn SongDecoder>, OpenError> {
+        Ok(Box::new(FileDecoder::new(path).map_err(OpenError::File)?))
+    }
+}
+
+/// a kira decoder that reads a audio file as it plays
+pub struct FileDecoder {
+    /// the container reader (gives us packets)
+    format_reader: Box<dyn FormatReader>,
+    /// the codec decoder (turns packets into samples)
+    decoder: Box<dyn SymphoniaDecoder>,
+    /// the id of the track we are playing within the file
+    track_id: u32,
+    /// the sample rate of the file
+    sample_rate: u32,
+    /// the total number of frames in the file
+    num_frames: usize,
+    /// reused buffer that symphonia copies samples into
+    buffer: Option<SampleBuffer<f32>>,
+    /// the tags we found while opening the file
+    tags: Tags,
+}
+
+impl FileDecoder {
+    /// opens the file at `path` and gets it ready to decode
+    pub fn new(path: &Path) -> Result<FileDecoder, FromFileError> {
+        let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        // give symphonia the extension as a hint so it does not have to guess as hard
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|x| x.to_str()) {
+            hint.with_extension(ext);
+        }
+        let mut probed = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+        let mut format_reader = probed.format;
+        // tags can be in the container (eg: flac/vorbis comments) or in front of it (eg: id3 on a mp3). we want both
+        let mut tags = Tags::default();
+        if let Some(revision) = format_reader.metadata().skip_to_latest() {
+            tags.merge(revision);
+        }
+        if let Some(revision) = probed.metadata.get().as_mut().and_then(|m| m.skip_to_latest()) {
+            tags.merge(revision);
+        }
+        let track = format_reader.default_track().ok_or(FromFileError::NoDefaultTrack)?;
+        let sample_rate = track.codec_params.sample_rate.ok_or(FromFileError::UnknownSampleRate)?;
+        let num_frames = track.codec_params.n_frames.ok_or(FromFileError::UnknownDuration)? as usize;
+        let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+        let track_id = track.id;
+        Ok(FileDecoder {
+            format_reader,
+            decoder,
+            track_id,
+            sample_rate,
+            num_frames,
+            buffer: None,
+            tags,
+        })
+    }
+}
+
+impl SongDecoder for FileDecoder {
+    fn tags(&self) -> Tags {
+        self.tags.clone()
+    }
+}
+
+impl Decoder for FileDecoder {
+    type Error = FromFileError;
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    fn decode(&mut self) -> Result<Vec<Frame>, Self::Error> {
+        loop {
+            let packet = match self.format_reader.next_packet() {
+                Ok(packet) => packet,
+                // out of packets. kira stops on its own once it reaches `num_frames`, which can be a tiny bit more
+                // than what is actually in the file. so pad with silence instead of erroring
+                Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(vec![Frame::ZERO; SILENCE_CHUNK]),
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue; // not our track (eg: a video stream or a second audio track)
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(_)) => continue, // a corrupt packet. symphonia says to just skip it
+                Err(e) => return Err(e.into()),
+            };
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            if channels == 0 {
+                return Err(FromFileError::UnsupportedChannelConfiguration);
+            }
+            // (re)make the buffer if it is too small for this packet
+            if self.buffer.as_ref().is_none_or(|b| b.capacity() < decoded.capacity() * channels) {
+                self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let buffer = self.buffer.as_mut().unwrap();
+            buffer.copy_interleaved_ref(decoded);
+            return Ok(match channels {
+                1 => buffer.samples().iter().map(|&x| Frame::from_mono(x)).collect(),
+                // stereo (and anything with more channels gets just its front left/right)
+                _ => buffer.samples().chunks_exact(channels).map(|x| Frame { left: x[0], right: x[1] }).collect(),
+            });
+        }
+    }
+
+    fn seek(&mut self, index: usize) -> Result<usize, Self::Error> {
+        let seeked = self.format_reader.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp { ts: index as u64, track_id: self.track_id },
+        )?;
+        self.decoder.reset(); // the decoder has state from before the seek that we need to throw away
+        Ok(seeked.actual_ts as usize)
+    }
+}
@@ -0,0 +1,105 @@
+// which files a folder scan keeps. only things we can play (or playlists) get in, then the `--include`/`--exclude`
+// globs get a say. whatever is left out is counted so we can say what was skipped instead of failing on it at play time
+//
+// globs: `*` is anything but a `/`, `**` is anything at all (so `**/` is any number of folders) and `?` is one character.
+// a glob without a `/` is matched against the file name, one with a `/` against the whole path
+
+use std::{collections::BTreeMap, path::Path, sync::{Mutex, OnceLock}};
+
+use crate::format::is_playable;
+
+/// the playlist formats `get_songs` expands (see `playlist::read`)
+const PLAYLIST_FORMATS: &[&str] = &["m3u", "m3u8", "pls", "xspf", "cue"];
+
+/// the include and exclude globs
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// if there are any, files have to match one of these
+    pub include: Vec<String>,
+    /// files that match any of these are left out
+    pub exclude: Vec<String>,
+}
+
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+/// how many files were skipped since the last report, and why
+static SKIPPED: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// sets the globs. call once at start up
+pub fn set(filter: Filter) {
+    let _ = FILTER.set(filter);
+}
+
+/// matches `text` against a glob
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| {
+            glob(rest, &text[i..])
+                // `**/` can be no folders at all, but only at the start of a folder name. not half way through one
+                || (rest.first() == Some(&'/') && (i == 0 || text[i - 1] == '/') && glob(&rest[1..], &text[i..]))
+        }),
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob(rest, &text[i..])),
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+/// whether `path` matches `pattern` (see the top of the file)
+fn matches(pattern: &str, path: &Path) -> bool {
+    let text = if pattern.contains('/') { path.to_string_lossy() } else { path.file_name().unwrap_or_default().to_string_lossy() };
+    glob(&pattern.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>())
+}
+
+/// whether a file found in a folder should go in the queue. counts it as skipped if not
+pub fn keep(path: &Path) -> bool {
+    let ext = path.extension().map_or(String::new(), |x| x.to_string_lossy().to_lowercase());
+    let reason = if !is_playable(&ext) && !PLAYLIST_FORMATS.contains(&ext.as_str()) {
+        if ext.is_empty() { "no extension".to_string() } else { format!(".{ext}") }
+    } else {
+        let filter = FILTER.get_or_init(Filter::default);
+        if !filter.include.is_empty() && !filter.include.iter().any(|glob| matches(glob, path)) {
+            "not included".to_string()
+        } else if filter.exclude.iter().any(|glob| matches(glob, path)) {
+            "excluded".to_string()
+        } else {
+            return true;
+        }
+    };
+    *SKIPPED.lock().unwrap().entry(reason).or_default() += 1;
+    false
+}
+
+/// prints what was skipped since the last report (if anything was) and starts counting again
+pub fn report() {
+    let skipped = std::mem::take(&mut *SKIPPED.lock().unwrap());
+    if skipped.is_empty() {
+        return;
+    }
+    let total: usize = skipped.values().sum();
+    let reasons: Vec<String> = skipped.into_iter().map(|(reason, count)| format!("{count} {reason}")).collect();
+    println!("skipped {total} files: {}", reasons.join(", "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_star_slash_only_matches_whole_folders() {
+        assert!(matches("**/Scans/**", Path::new("/music/X/Scans/a.jpg")));
+        assert!(matches("**/Scans/**", Path::new("Scans/a.jpg")));
+        assert!(!matches("**/Scans/**", Path::new("/music/X/MyScans/a.jpg")));
+        assert!(matches("**/live/*.flac", Path::new("/music/live/x.flac")));
+        assert!(!matches("**/live/*.flac", Path::new("/music/alive/x.flac")));
+    }
+
+    #[test]
+    fn single_star_stays_in_one_folder() {
+        assert!(matches("/music/*.flac", Path::new("/music/x.flac")));
+        assert!(!matches("/music/*.flac", Path::new("/music/a/x.flac")));
+        assert!(matches("*.flac", Path::new("/music/a/x.flac"))); // no `/`, so just the file name
+    }
+}
@@ -0,0 +1,90 @@
+// the formats we can play. each one registers here with the extensions it goes by and a way to recognise its files
+// from their first few bytes, so a file with the wrong (or no) extension still gets the right decoder.
+// the built in ones are symphonia's (`filedecoder.rs`) and libopenmpt's (`moddecoder.rs`), registered in `main`.
+// a new format is a `Format`, a `SongDecoder` and a `register` call. nothing else looks at extensions or decoder types
+
+use std::{fs::File, io::Read, path::Path, sync::{Arc, Mutex, RwLock}, time::Duration};
+
+use kira::sound::{streaming::Decoder, FromFileError};
+
+use crate::{decoder::OpenError, moddecoder::TrackerState, tags::Tags};
+
+/// how much of a file `probe` gets to look at. libopenmpt wants this much for some of its formats
+pub const HEADER_LENGTH: usize = 2048;
+
+/// what a format's `open` hands back. a kira decoder plus what the player wants to know about the song.
+/// only `tags` has to be written, the rest are for formats that have something special to say
+pub trait SongDecoder: Decoder<Error = FromFileError> + Send {
+    /// the tags in the file
+    fn tags(&self) -> Tags;
+
+    /// how long the song naturally is. tracker modules are counted once through, however many times they are set to play
+    fn length(&self) -> Duration {
+        Duration::from_secs_f64(self.num_frames() as f64 / self.sample_rate() as f64)
+    }
+
+    /// how long to fade out at the end of the song, for songs that do not end by themselves
+    fn fade_out(&self) -> Option<Duration> {
+        None
+    }
+
+    /// what a tracker module is up to as it plays (and which channels to mute)
+    fn tracker(&self) -> Option<Arc<Mutex<TrackerState>>> {
+        None
+    }
+}
+
+/// a kind of file we can play
+pub trait Format: Send + Sync {
+    /// the extensions files of this format go by, in lower case without the dot
+    fn extensions(&self) -> &[String];
+
+    /// whether a file looks like this format. `header` is the start of it (shorter if the file is), `file_size` all of it
+    fn probe(&self, header: &[u8], file_size: u64) -> bool;
+
+    /// opens a file for playback. `subsong` is for formats that have more than one song in a file
+    fn open(&self, path: &Path, subsong: Option<i32>) -> Result<Box<dyn SongDecoder>, OpenError>;
+
+    /// how many songs are in the file, for formats that can have more than one. `None` if it is just the one
+    fn subsongs(&self, _path: &Path) -> Option<i32> {
+        None
+    }
+}
+
+/// every registered format, in the order they were registered
+static FORMATS: RwLock<Vec<Arc<dyn Format>>> = RwLock::new(Vec::new());
+
+/// adds a format. formats are tried in the order they were registered
+pub fn register(format: impl Format + 'static) {
+    FORMATS.write().unwrap().push(Arc::new(format));
+}
+
+/// the format that goes by this extension, if any
+pub fn by_extension(ext: &str) -> Option<Arc<dyn Format>> {
+    let ext = ext.to_lowercase();
+    FORMATS.read().unwrap().iter().find(|format| format.extensions().contains(&ext)).cloned()
+}
+
+/// whether we can play files with this extension
+pub fn is_playable(ext: &str) -> bool {
+    by_extension(ext).is_some()
+}
+
+/// the format of the file at `path`. the one its extension says if the file agrees, otherwise whichever one recognises
+/// it, otherwise the extension's (for files that can not be read, or formats that can not be told by their header)
+pub fn find(path: &Path) -> Option<Arc<dyn Format>> {
+    let ext = path.extension().map_or(String::new(), |x| x.to_string_lossy().to_lowercase());
+    let mut header = Vec::with_capacity(HEADER_LENGTH);
+    let file_size = File::open(path).and_then(|file| {
+        let size = file.metadata()?.len();
+        file.take(HEADER_LENGTH as u64).read_to_end(&mut header)?;
+        Ok(size)
+    });
+    let formats = FORMATS.read().unwrap();
+    let named = formats.iter().find(|format| format.extensions().contains(&ext));
+    let Ok(file_size) = file_size else { return named.cloned() };
+    if named.is_some_and(|format| format.probe(&header, file_size)) {
+        return named.cloned();
+    }
+    formats.iter().find(|format| format.probe(&header, file_size)).or(named).cloned()
+}
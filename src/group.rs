@@ -0,0 +1,187 @@
+// `@` group lines. a group is a bunch of songs that always play in order, straight on from each other, even when shuffling.
+// e.g. Bergentrückung + ASGORE from undertale:
+//
+//   @ "Bergentrückung.ogg" "ASGORE.ogg"  # these two belong together
+//
+// the grammar is:
+//
+//   line    = '@' items [comment]
+//   items   = { item }
+//   item    = path | '(' items ')'
+//   path    = '"' { any char but '"' or '\' | '\' any char } '"'
+//   comment = '#' { any char }
+//
+// whitespace between items is ignored. a path can be a song, a folder or a playlist, they get expanded in place.
+// groups can be nested with `( )` so a group line can be built out of other groups, they play in order like everything else
+
+use std::{fmt, path::{Path, PathBuf}};
+
+use crate::{get_songs, Song};
+
+/// what is wrong with a group line, and where
+#[derive(Debug)]
+pub struct ParseError {
+    /// the line of the playlist (1 for a group given on the command line)
+    pub line: usize,
+    /// the column the problem starts at (in characters, from 1)
+    pub column: usize,
+    /// what is wrong
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// one thing in a group
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    /// a path, as written
+    Path(String),
+    /// a nested group
+    Group(Vec<Item>),
+}
+
+/// walks through a line a character at a time, keeping track of the column
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, column: usize, message: impl Into<String>) -> ParseError {
+        ParseError { line: self.line, column, message: message.into() }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.column += 1;
+        self.chars.next()
+    }
+
+    /// skips whitespace and comments. returns the next character without using it up
+    fn peek(&mut self) -> Option<char> {
+        while let Some(&c) = self.chars.peek() {
+            if c == '#' {
+                while self.next().is_some() {} // a comment runs to the end of the line
+            } else if c.is_whitespace() {
+                self.next();
+            } else {
+                return Some(c);
+            }
+        }
+        None
+    }
+
+    /// parses items until the end of the line (or the `)` that closes the group we are in)
+    fn items(&mut self, open: Option<usize>) -> Result<Vec<Item>, ParseError> {
+        let mut items = vec![];
+        loop {
+            let next = self.peek();
+            let column = self.column + 1;
+            match next {
+                None => return match open {
+                    Some(open) => Err(self.error(open, "this `(` is never closed")),
+                    None => Ok(items),
+                },
+                Some(')') => {
+                    self.next();
+                    return match open {
+                        Some(_) => Ok(items),
+                        None => Err(self.error(column, "`)` without a `(` before it")),
+                    };
+                }
+                Some('(') => {
+                    self.next();
+                    let group = self.items(Some(column))?;
+                    if group.is_empty() {
+                        return Err(self.error(column, "empty group"));
+                    }
+                    items.push(Item::Group(group));
+                }
+                Some('"') => {
+                    self.next();
+                    items.push(Item::Path(self.path(column)?));
+                }
+                Some(c) => return Err(self.error(column, format!("expected a quoted path, found `{c}`"))),
+            }
+        }
+    }
+
+    /// parses the rest of a quoted path (the opening quote is at `open`)
+    fn path(&mut self, open: usize) -> Result<String, ParseError> {
+        let mut path = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error(open, "this quote is never closed")),
+                Some('"') => return Ok(path),
+                Some('\\') => match self.next() {
+                    Some(c) => path.push(c),
+                    None => return Err(self.error(open, "this quote is never closed")),
+                },
+                Some(c) => path.push(c),
+            }
+        }
+    }
+}
+
+/// parses a `@` group line. `line` is only used for errors
+pub fn parse(text: &str, line: usize) -> Result<Vec<Item>, ParseError> {
+    let mut parser = Parser { chars: text.chars().peekable(), line, column: 0 };
+    if parser.peek() != Some('@') {
+        return Err(parser.error(parser.column + 1, "group lines start with `@`"));
+    }
+    let at = parser.column + 1;
+    parser.next();
+    let items = parser.items(None)?;
+    if items.is_empty() {
+        return Err(parser.error(at, "empty group"));
+    }
+    Ok(items)
+}
+
+/// puts a path in quotes for a `@` line, escaping anything the parser would trip over. relative paths are made
+/// absolute first (relative to the folder we are in)
+pub fn quote(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or(path.to_path_buf());
+    let path = path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{path}\"")
+}
+
+/// the same group line with every path in it made absolute, so it means the same thing to a player that is running
+/// in another folder
+pub fn absolute(text: &str) -> Result<String, ParseError> {
+    fn write(items: &[Item]) -> String {
+        let words: Vec<String> = items.iter().map(|item| match item {
+            Item::Path(path) => quote(Path::new(path)),
+            Item::Group(items) => format!("({})", write(items)),
+        }).collect();
+        words.join(" ")
+    }
+    Ok(format!("@ {}", write(&parse(text, 1)?)))
+}
+
+/// parses a `@` group line and expands it into a single queue entry holding all of its songs.
+/// relative paths are relative to `folder` (the playlist's folder)
+pub fn load(text: &str, line: usize, folder: &Path) -> Result<Song, ParseError> {
+    fn expand(items: Vec<Item>, folder: &Path, members: &mut Vec<Song>) {
+        for item in items {
+            match item {
+                Item::Path(path) => {
+                    let mut songs = get_songs(&folder.join(path));
+                    songs.reverse(); // get_songs hands them back reversed
+                    members.extend(songs.into_iter().flat_map(|song| {
+                        // a playlist in a group can have groups of its own. they all run together here
+                        if song.members.is_empty() { vec![song] } else { song.members }
+                    }));
+                }
+                Item::Group(items) => expand(items, folder, members),
+            }
+        }
+    }
+    let mut members = vec![];
+    expand(parse(text, line)?, folder, &mut members);
+    Ok(Song { members, ..Song::from(PathBuf::from(text.trim())) })
+}
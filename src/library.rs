@@ -0,0 +1,292 @@
+// the library index. remembers what is in every folder we have played from, and the tags and length of every file,
+// so big (or network mounted) libraries do not have to be walked and probed every time the queue is filled.
+//
+// folders are only listed again when their mtime changes, and files are only opened again when theirs does.
+// the index is a text file in the cache folder, one line per folder or file, with tab separated fields. paths are
+// always absolute, since the same index is used from whatever folder the player is started in:
+//
+//   D  <path>  <mtime>  <name>...                                   a folder and what is in it
+//   F  <path>  <mtime>  <format>  <seconds>  <title>  <artist>  <album>   a file (empty fields are unknown)
+//   L  <path>  <mtime>  <part>  <lufs>                              the measured loudness of a file (or part of it, see
+//                                                                   `replaygain::part`). empty if it could not be measured
+
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, sync::{Mutex, OnceLock}, time::UNIX_EPOCH};
+
+use crate::{decoder::TrackDecoder, dirs::cache_dir, playlist::Hint};
+
+/// the first line of the index. bump the number when the format changes so old indexes get thrown away
+const HEADER: &str = "new_music_player library 2";
+
+/// what the index knows about a file
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    /// when the file was last changed, in nanoseconds since the epoch
+    pub mtime: u64,
+    /// the extension, in lower case
+    pub format: String,
+    /// how long it is in seconds. `None` if it could not be opened
+    pub duration: Option<f64>,
+    /// the title tag
+    pub title: Option<String>,
+    /// the artist tag
+    pub artist: Option<String>,
+    /// the album tag
+    pub album: Option<String>,
+}
+
+impl Entry {
+    /// the entry as a playlist hint, so the queue can show it without opening the file
+    pub fn hint(&self) -> Hint {
+        Hint { title: self.title.clone(), artist: self.artist.clone(), album: self.album.clone(), duration: self.duration }
+    }
+}
+
+/// a folder the index knows the contents of
+#[derive(Debug, Clone)]
+struct Folder {
+    mtime: u64,
+    /// the names of the files and folders in it, sorted
+    names: Vec<String>,
+}
+
+/// the whole index
+#[derive(Default)]
+pub struct Library {
+    files: HashMap<PathBuf, Entry>,
+    folders: HashMap<PathBuf, Folder>,
+    /// measured loudness by file and part, with the mtime of the file when it was measured
+    loudness: HashMap<(PathBuf, String), (u64, Option<f64>)>,
+    /// true if something changed since it was loaded or saved
+    dirty: bool,
+}
+
+/// the index, loaded the first time it is needed
+static LIBRARY: OnceLock<Mutex<Library>> = OnceLock::new();
+
+/// the index, loading it from disk if this is the first time
+pub fn library() -> &'static Mutex<Library> {
+    LIBRARY.get_or_init(|| Mutex::new(Library::load()))
+}
+
+fn index_path() -> PathBuf {
+    cache_dir().join("library.tsv")
+}
+
+/// `path` made absolute, which is how the index keys everything
+fn key(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or(path.to_path_buf())
+}
+
+/// when `path` was last changed, in nanoseconds since the epoch
+fn mtime_of(metadata: &fs::Metadata) -> u64 {
+    metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// escapes a field so it can not break the line up
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// undoes `escape`
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => break,
+        }
+    }
+    out
+}
+
+/// a field that may be unknown (empty)
+fn optional(field: Option<&&str>) -> Option<String> {
+    field.filter(|f| !f.is_empty()).map(|f| unescape(f))
+}
+
+impl Library {
+    /// reads the index from disk. a missing or old index is just empty
+    fn load() -> Library {
+        let mut library = Library::default();
+        let Ok(text) = fs::read_to_string(index_path()) else { return library };
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return library;
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (Some(kind), Some(path), Some(Ok(mtime))) = (fields.first(), fields.get(1), fields.get(2).map(|m| m.parse())) else { continue };
+            let path = PathBuf::from(unescape(path));
+            match *kind {
+                "D" => {
+                    let names = fields[3..].iter().map(|name| unescape(name)).collect();
+                    library.folders.insert(path, Folder { mtime, names });
+                }
+                "L" => {
+                    let (Some(part), Some(lufs)) = (fields.get(3), fields.get(4)) else { continue };
+                    library.loudness.insert((path, unescape(part)), (mtime, lufs.parse().ok()));
+                }
+                "F" => {
+                    library.files.insert(path, Entry {
+                        mtime,
+                        format: optional(fields.get(3)).unwrap_or_default(),
+                        duration: optional(fields.get(4)).and_then(|d| d.parse().ok()),
+                        title: optional(fields.get(5)),
+                        artist: optional(fields.get(6)),
+                        album: optional(fields.get(7)),
+                    });
+                }
+                _ => {}
+            }
+        }
+        library
+    }
+
+    /// writes the index to disk if it changed
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut out = format!("{HEADER}\n");
+        for (path, folder) in &self.folders {
+            out += &format!("D\t{}\t{}", escape(&path.to_string_lossy()), folder.mtime);
+            for name in &folder.names {
+                out += &format!("\t{}", escape(name));
+            }
+            out += "\n";
+        }
+        for (path, entry) in &self.files {
+            let field = |f: &Option<String>| f.as_deref().map_or(String::new(), escape);
+            out += &format!(
+                "F\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                escape(&path.to_string_lossy()), entry.mtime, escape(&entry.format),
+                entry.duration.map_or(String::new(), |d| d.to_string()),
+                field(&entry.title), field(&entry.artist), field(&entry.album),
+            );
+        }
+        for ((path, part), (mtime, lufs)) in &self.loudness {
+            let lufs = lufs.map_or(String::new(), |l| l.to_string());
+            out += &format!("L\t{}\t{mtime}\t{}\t{lufs}\n", escape(&path.to_string_lossy()), escape(part));
+        }
+        fs::create_dir_all(cache_dir())?;
+        // write to the side and move it over, so a crash half way through does not leave a broken index
+        let temp = index_path().with_extension("tsv.new");
+        fs::write(&temp, out)?;
+        fs::rename(temp, index_path())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// what the index knows about `path` (without looking at the file)
+    pub fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.files.get(&key(path))
+    }
+
+    /// what the index knows about the file at `path`, opening it first if the index does not know it (or it changed)
+    pub fn file(&mut self, path: &Path) -> Entry {
+        let path = &key(path);
+        let mtime = fs::metadata(path).map_or(0, |metadata| mtime_of(&metadata));
+        if self.files.get(path).is_none_or(|entry| entry.mtime != mtime) {
+            self.files.insert(path.to_path_buf(), probe(path, mtime));
+            self.dirty = true;
+        }
+        self.files[path].clone()
+    }
+
+    /// the loudness (in LUFS) measured for `part` of the file at `path`. `None` if it was never measured (or the file
+    /// changed since), `Some(None)` if it could not be
+    pub fn loudness(&self, path: &Path, part: &str) -> Option<Option<f64>> {
+        let mtime = fs::metadata(path).map_or(0, |metadata| mtime_of(&metadata));
+        let &(measured, lufs) = self.loudness.get(&(key(path), part.to_string()))?;
+        (measured == mtime).then_some(lufs)
+    }
+
+    /// remembers the loudness measured for `part` of the file at `path`
+    pub fn set_loudness(&mut self, path: &Path, part: &str, lufs: Option<f64>) {
+        let mtime = fs::metadata(path).map_or(0, |metadata| mtime_of(&metadata));
+        self.loudness.insert((key(path), part.to_string()), (mtime, lufs));
+        self.dirty = true;
+    }
+
+    /// every file the index knows about, sorted
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// every file under `folder` (however deep), sorted and with absolute paths. brings the index up to date on the way
+    pub fn files_in(&mut self, folder: &Path) -> Vec<PathBuf> {
+        let folder = &key(folder);
+        let mut files = vec![];
+        let mut probed = 0;
+        self.scan(folder, &mut files, &mut probed);
+        if probed > 0 {
+            println!("indexed {probed} new or changed files in {folder:?}");
+        }
+        files.sort();
+        files
+    }
+
+    fn scan(&mut self, folder: &Path, files: &mut Vec<PathBuf>, probed: &mut usize) {
+        let Ok(metadata) = fs::metadata(folder) else { return };
+        let mtime = mtime_of(&metadata);
+        let names = match self.folders.get(folder) {
+            Some(known) if known.mtime == mtime => known.names.clone(), // nothing was added or removed
+            known => {
+                let mut names: Vec<String> = fs::read_dir(folder).into_iter().flatten().flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect();
+                names.sort();
+                // forget what was in here but is not any more
+                for gone in known.map(|k| k.names.clone()).unwrap_or_default().iter().filter(|n| !names.contains(n)) {
+                    let gone = folder.join(gone);
+                    self.files.retain(|path, _| !path.starts_with(&gone));
+                    self.folders.retain(|path, _| !path.starts_with(&gone));
+                    self.loudness.retain(|(path, _), _| !path.starts_with(&gone));
+                }
+                self.folders.insert(folder.to_path_buf(), Folder { mtime, names: names.clone() });
+                self.dirty = true;
+                names
+            }
+        };
+        for name in names {
+            let path = folder.join(name);
+            let Ok(metadata) = fs::metadata(&path) else { continue };
+            if metadata.is_dir() {
+                self.scan(&path, files, probed);
+                continue;
+            }
+            let mtime = mtime_of(&metadata);
+            if self.files.get(&path).is_none_or(|entry| entry.mtime != mtime) {
+                self.files.insert(path.clone(), probe(&path, mtime));
+                self.dirty = true;
+                *probed += 1;
+            }
+            files.push(path);
+        }
+    }
+}
+
+/// opens a file to read its tags and length
+fn probe(path: &Path, mtime: u64) -> Entry {
+    let format = path.extension().map_or(String::new(), |x| x.to_string_lossy().to_lowercase());
+    let Ok(decoder) = TrackDecoder::open(path) else { return Entry { mtime, format, ..Default::default() } };
+    let tags = decoder.tags();
+    Entry {
+        mtime,
+        format,
+        duration: Some(decoder.length().as_secs_f64()),
+        title: tags.title,
+        artist: tags.artist,
+        album: tags.album,
+    }
+}
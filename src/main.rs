@@ -1,51 +1,109 @@
 // a bunch of impports from the standard library. in order...
 // we import mutex/once lock for some globals (statics)
-// vecDequeue since it is efficent to push/pop from front unlike vec which makes it slow.
+// vecDequeue since it is efficent to push/pop from front unlike vec which makes it slow. hashset to spot CUE-split files
 // fmt so we can implement Debug on some of our types
-// fs so we can read audio files to bytes
 // path(buf) for the ability to actually read files
 // OsStr is needed for some souvlaki stuff (that or it was pathbuf. it has been soo long)
-// process stuff so we can exit early, and command so that we can run `openmpt123` as subprocess
+// process stuff so we can exit early
 // duration so it can manage delays/times with souvlaki
-use std::{sync::{Mutex, OnceLock}, collections::VecDeque, fmt, fs, path::{Path, PathBuf}, ffi::OsStr, process::{exit, Command, Stdio}, time::Duration, str::FromStr};
+use std::{sync::{Arc, Mutex, OnceLock}, collections::{HashSet, VecDeque}, fmt, path::{Path, PathBuf}, ffi::OsStr, process::exit, time::Duration};
 
 // we then import clap so making CLI args are easy
-use clap::{command, Parser};
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser};
 // kira is a audio manager crate that allows us to play audio...
-use kira::{manager::{AudioManager, backend::DefaultBackend, AudioManagerSettings}, sound::{PlaybackState, static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings}}, tween::Tween};
-// I would use more functions from openmpt but the api is broken.
-use openmpt::info::get_supported_extensions;
+use kira::{manager::{AudioManager, backend::DefaultBackend, AudioManagerSettings}, sound::{PlaybackState, FromFileError, streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings}}, tween::Tween, clock::{ClockHandle, ClockSpeed}, Volume};
+// cpal is what kira uses to talk to the sound card. we only use it to ask what sample rate the output is
+use cpal::traits::{DeviceTrait, HostTrait};
 // souvlaki provides cross-platform media controls
 use souvlaki::{PlatformConfig, MediaControls, MediaMetadata, MediaControlEvent, MediaPosition, SeekDirection};
-// and we use rand to shuffle the list.
-use rand::thread_rng;
-use rand::seq::SliceRandom;
-
-/// takes a iterator of chars and produces a list of strings that have been surrounded by quotes
-fn quoted<T>(tgt: T) -> Vec<String> where T: Iterator<Item = char> {
-    let mut res = vec![]; // the result list
-    let mut capture = false; //if we are within a "
-    let mut buf = vec![]; //the current buffer of chars that will be en-stringed
-    let mut escaped = false; // if the next char is escaped (allows file names to contain with a `"` by escaping it)
-    for ch in tgt { //over each char
-        if escaped { //check if we escaped it
-            buf.push(ch); //push char directly
-            escaped = false //stop the escape
-        } else if ch == '"' { // if char is a quote
-            if capture { // check if capture is set
-                let str: String = buf.iter().collect(); // collect the buffer into a string
-                res.push(str); // add string to the result list
-            } else {
-                buf.clear() // clear the buffer (since it is "dead" space between words)
-            }
-            capture = !capture; // invert capture so that on first quote we start capturing, and on second we stop capturing and add string to results
-        } else if ch == '\\' { //if char is a \ we escape the next char
-            escaped = true
-        } else {
-            buf.push(ch) // we just push the char since it needs no special handling
-        }
-    };
-    res //return the results
+// and we use rand to shuffle the list. a seeded StdRng so a shuffle can be played again
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+
+// our own decoders. one for regular files, one for tracker music, and one that picks between them
+mod decoder;
+mod filedecoder;
+mod moddecoder;
+// which decoder plays which files
+mod format;
+mod openmpt_ext;
+use decoder::{Region, TrackDecoder};
+use moddecoder::{Endless, Interpolation, RenderSettings, TrackerState};
+// reading tags, and evening out the volume of songs with them
+mod replaygain;
+mod tags;
+// cover art, and the folders we keep it (and other things) in
+mod cover;
+mod dirs;
+// the terminal UI
+mod tui;
+// playlist files, and the `@` group lines in them
+mod playlist;
+mod group;
+// saving the queue between runs
+mod session;
+// shuffling the queue
+mod shuffle;
+// the index of every folder and file we have played from, and searching it
+mod library;
+mod query;
+use query::Query;
+// which files folder scans keep
+mod filter;
+use shuffle::ShuffleMode;
+// the control socket, so scripts can drive the player
+#[cfg(unix)]
+mod control;
+use replaygain::{gain_db, ReplayGainMode};
+use tags::Tags;
+use playlist::Hint;
+
+/// how many seconds before the current song ends that we open the next one and schedule it
+const PRELOAD_SECONDS: f64 = 5.0;
+
+/// how often the session is saved while playing (it is saved on quit too)
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(15);
+/// how many of the upcoming songs get their loudness measured ahead of time (with `--analyze-loudness`)
+const MEASURE_AHEAD: usize = 3;
+
+/// a single entry in the queue
+#[derive(Debug, Clone, PartialEq)]
+struct Song {
+    /// the path to the song (or the `@` line of a group)
+    path: PathBuf,
+    /// true if this song came out of a `@` line and runs straight on from the song before it (so no crossfade)
+    grouped: bool,
+    /// the part of the file to play, for tracks of a CUE sheet. `None` plays all of it
+    region: Option<Region>,
+    /// the subsong to play, if it is a tracker module. `None` plays the one from the render settings
+    subsong: Option<i32>,
+    /// what the playlist the song came from said about it
+    hint: Option<Hint>,
+    /// the songs of a `@` group, in order. they are kept together in one entry so shuffling does not split them up
+    members: Vec<Song>,
+}
+
+impl From<PathBuf> for Song {
+    fn from(path: PathBuf) -> Song {
+        Song { path, grouped: false, region: None, subsong: None, hint: None, members: vec![] }
+    }
+}
+
+/// a song that has been opened ahead of time and scheduled to start on the exact sample the current song ends
+struct Preloaded {
+    /// the song
+    song: Song,
+    /// the kira handle of the song (it is allready "playing" but waiting on the clock)
+    handle: StreamingSoundHandle<FromFileError>,
+    /// how long the song is
+    duration: Duration,
+    /// the replaygain the song is played at (in dB)
+    gain: f64,
+    /// how long the song fades out for at the end, if it does
+    fade_out: Option<Duration>,
+    /// what the song is up to, if it is a tracker module
+    tracker: Option<Arc<Mutex<TrackerState>>>,
+    /// the song's tags
+    tags: Tags,
 }
 
 /// global struct for the state of the media player
@@ -56,12 +114,42 @@ struct Status {
     controls: MediaControls,
     /// the instance of the kira audio manager
     manager: AudioManager,
-    /// the upcoming list of paths to play as music
-    upcoming: VecDeque<PathBuf>,
+    /// the upcoming list of songs to play as music
+    upcoming: VecDeque<Song>,
     /// a size-limited queue that acts as a "lookback" buffer so you can play previous songs
-    lookback: VecDeque<PathBuf>,
+    lookback: VecDeque<Song>,
     /// this is a kira soundhandle. if audio is playing this should be `Some`
-    handle: Option<StaticSoundHandle>
+    handle: Option<StreamingSoundHandle<FromFileError>>,
+    /// how long the current song is
+    duration: Duration,
+    /// the next song, allready opened and waiting for the current one to end
+    preloaded: Option<Preloaded>,
+    /// a kira clock that ticks once per output sample. used to start the next song right as the current one ends
+    clock: ClockHandle,
+    /// how long to crossfade between songs. zero turns crossfading off
+    crossfade: Duration,
+    /// the replaygain the current song is played at (in dB)
+    gain: f64,
+    /// how long the current song fades out for at the end (tracker modules that loop forever)
+    fade_out: Option<Duration>,
+    /// true once the current song's fade out has started
+    fading: bool,
+    /// what the current song is up to (and which channels are muted), if it is a tracker module
+    tracker: Option<Arc<Mutex<TrackerState>>>,
+    /// the tags of the current song
+    tags: Tags,
+    /// which replaygain tags to use
+    replaygain: ReplayGainMode,
+    /// whether to measure the loudness of songs that have no replaygain tags
+    analyze_loudness: bool,
+    /// the volume of everything (as a factor, 1.0 is full volume)
+    volume: f64,
+    /// what to keep together when shuffling
+    shuffle: ShuffleMode,
+    /// where shuffles get their randomness from. seeded so a shuffle order can be repeated
+    rng: StdRng,
+    /// whether shuffles keep recently played songs (and songs by the same artist) apart
+    no_repeat: bool,
 }
 
 /// debug formatter for printing status mid-run (ignores the handle and manager and controlls field)
@@ -74,93 +162,352 @@ impl fmt::Debug for Status {
 impl Status {
     /// stops playing audio if it is playing.
     fn stopit(&mut self) {
-        if self.handle.is_some() {
-            self.handle.as_mut().unwrap().stop(Tween::default()).unwrap();
+        self.cancel_preload();
+        if let Some(handle) = self.handle.as_mut() {
+            handle.stop(Tween::default()).unwrap();
+        }
+    }
+    /// pops the next song off of `upcoming`, expanding any `@` lines into their songs along the way
+    fn next_song(&mut self) -> Option<Song> {
+        loop {
+            let upcoming = self.upcoming.pop_front()?;
+            //check if it is a `@` group in which case it is a special case (see `group.rs`)
+            //special case as for eg: if the song is shuffled but I want these songs to be played in order. eg: Bergentrückung + ASGORE from undertale
+            if !upcoming.members.is_empty() {
+                // every song but the first runs straight on from the one before it. reversed so they are pushed onto song queue right
+                for (i, song) in upcoming.members.into_iter().enumerate().rev() {
+                    self.upcoming.push_front(Song { grouped: i != 0, ..song })// put them on here
+                };
+                continue; // head STRAIGHT to the first song of the group (we dont return the group. it gets buggy if we do)
+            };
+            return Some(upcoming);
+        }
+    }
+    /// how long to crossfade into `song`. songs inside a `@` line are meant to run straight on so they never crossfade
+    fn fade_into(&self, song: &Song) -> Duration {
+        if song.grouped { Duration::ZERO } else { self.crossfade }
+    }
+    /// opens a decoder for `song`. prints why and returns `None` if it cannot be played
+    fn open_song(song: &Song) -> Option<TrackDecoder> {
+        let path = song.path.as_path();
+        if !path.exists() { // if path does not exists we just exit so it can start next song (or stop the music player if that was the last one)
+            println!("Path {path:?} does not exists. Skipping"); // let the user in terminal know that path does not exists
+            return None
+        }
+        // open a streaming decoder for the song. nothing is decoded yet, it gets decoded bit by bit as it plays
+        match TrackDecoder::open_part(path, song.region, song.subsong) {
+            Ok(decoder) => Some(decoder),
+            Err(e) => {
+                println!("{} file {}. SKIPPING",e,path.to_str().unwrap_or("!!failed to unwrap path as str!!"));
+                None // it failed to open song so we skip to next song
+            }
+        }
+    }
+    /// the tags of a song we just opened. embedded cover art is written out to the cache now, and songs without any get
+    /// the `cover.jpg` (or similar) next to them
+    fn tags_for(song: &Song, decoder: &TrackDecoder) -> Tags {
+        let mut tags = decoder.tags();
+        // the playlist may know what the song is called even if the file does not.
+        // for a CUE track the file's tags are for the whole rip, so the sheet wins there
+        if let Some(hint) = song.hint.clone() {
+            let prefer = |file: Option<String>, hint: Option<String>| if song.region.is_some() { hint.or(file) } else { file.or(hint) };
+            tags.title = prefer(tags.title.take(), hint.title);
+            tags.artist = prefer(tags.artist.take(), hint.artist);
+            tags.album = prefer(tags.album.take(), hint.album);
+        }
+        if tags.cover_url.is_none() {
+            tags.cover_url = tags.cover.as_ref().and_then(cover::cache).or_else(|| cover::folder_cover(&song.path));
+        }
+        tags
+    }
+    /// works out the replaygain (in dB) to play a song we just opened at
+    fn gain_for(&self, song: &Song, tags: &Tags) -> f64 {
+        if self.replaygain == ReplayGainMode::Off {
+            return 0.0;
+        }
+        let loudness = if self.analyze_loudness && tags.track_gain.is_none() && tags.album_gain.is_none() {
+            // no tags so we have to listen to the whole song ourselves. that happens in the background (see
+            // `replaygain.rs`) and until it is done the song plays as is
+            replaygain::loudness(song)
+        } else {
+            None
+        };
+        gain_db(tags, self.replaygain, loudness)
+    }
+    /// has the next few songs measured in the background, so their loudness is known by the time they play
+    fn measure_upcoming(&self) {
+        if !self.analyze_loudness || self.replaygain == ReplayGainMode::Off {
+            return;
+        }
+        let songs = self.upcoming.iter().take(MEASURE_AHEAD)
+            .flat_map(|song| if song.members.is_empty() { std::slice::from_ref(song) } else { &song.members[..] });
+        for song in songs {
+            replaygain::request(song);
         }
     }
+    /// tells souvlaki about the song that just started
+    fn announce(&mut self, path: &Path) {
+        if let Err(e) = session::record_play(path) {
+            println!("could not write the play log: {e}");
+        }
+        // use the real tags if the song has them. the file name is better than nothing for the title
+        let meta = MediaMetadata {
+            title: self.tags.title.as_deref().or(path.file_name().unwrap().to_str()),
+            artist: self.tags.artist.as_deref(),
+            album: self.tags.album.as_deref(),
+            cover_url: self.tags.cover_url.as_deref(),
+            duration: Some(self.duration), //set metadata's duration for the song
+        };
+        println!("now playing: {} by {}", meta.title.unwrap_or("?"), meta.artist.unwrap_or("unknown"));
+        let _ = self.controls.set_metadata(meta); //set media metadata
+        let _ = self.controls.set_playback(souvlaki::MediaPlayback::Playing { progress: None }); //play the song (with no progress since we have not started)
+        println!("song is playing"); // notify user via text that song has started
+    }
     /// stops the current song and plays the next one 
     fn play_next_song(&mut self) {
-        self.stopit();
+        self.cancel_preload();
         println!("playing next song");
         //get the next song or if there is none stop the current song and exit
-        let upcoming = if let Some(upcoming) = self.upcoming.pop_front() {
+        let upcoming = if let Some(upcoming) = self.next_song() {
             upcoming //we have a next song
         } else {
             // there is no next song so we just silence the current one and return without the next one (other code detects that handle has stopped)
-            let _ = self.handle.as_mut().unwrap().stop(Tween::default());
+            self.stopit();
             return;
         };
 
-        //check if the name starts with a `@` in which case it is a special case
-        //special case as for eg: if the song is shuffled but I want these songs to be played in order. eg: Bergentrückung + ASGORE from undertale
-        if upcoming.to_string_lossy().starts_with('@') {
-            let mut words = quoted(upcoming.to_string_lossy().chars());// split the string into quoted words
-            words.reverse();//reverse so they are pushed onto song queue right
-            for song in words {
-                self.upcoming.push_front(song.into())// put them on here
-            };
-            self.play_next_song(); // head STRAIGHT to playing the next song
-            return; // so we dont push the @ line to the lookback (it gets buggy if we do)
-        };
+        // fade the current song out (over the crossfade, or instantly if there is none)
+        let fade = self.fade_into(&upcoming);
+        if let Some(handle) = self.handle.as_mut() {
+            let _ = handle.stop(if fade.is_zero() { Tween::default() } else { Tween { duration: fade, ..Default::default() } });
+        }
 
         //push the song to loopback so the back button works
         self.push_song_to_lookback(upcoming.clone());
 
         //turn the path back so it can be checked 
-        let path = Path::new(&upcoming);
+        let path = Path::new(&upcoming.path);
 
-        if !path.exists() { // if path does not exists we just exit so it can start next song (or stop the music player if that was the last one)
-            println!("Path {path:?} does not exists. Skipping"); // let the user in terminal know that path does not exists
-            return
+        let Some(decoder) = Self::open_song(&upcoming) else { return };
+        let tags = Self::tags_for(&upcoming, &decoder);
+        self.gain = self.gain_for(&upcoming, &tags);
+        self.tags = tags;
+        (self.fade_out, self.fading) = (decoder.fade_out(), false);
+        self.tracker = decoder.tracker();
+        let mut settings = StreamingSoundSettings::new().volume(Volume::Decibels(self.gain));
+        if !fade.is_zero() {
+            settings = settings.fade_in_tween(Tween { duration: fade, ..Default::default() });
         }
+        let sound = StreamingSoundData::from_decoder(decoder, settings);
+        self.duration = sound.duration();
 
-        //get path's extension. or default it to blank if it does not exists/cannot be turned into UTF-8
-        let ext = path.extension().unwrap_or(OsStr::new("")).to_str().unwrap_or("");
-        
-        #[allow(unused_mut)] // create a media metadata, must silence unused mut as a later assign to `meta.duration` fails if I do not define this as mutable
-        let mut meta = MediaMetadata {
-            title: path.file_name().unwrap().to_str(),
-            ..Default::default()
-        };
-
-        let sound = match ext {
-            "wav" | "mp3" | "flac" | "ogg" => { // known file type that kira supports directly so we play it
-                StaticSoundData::from_file(path, StaticSoundSettings::default()).unwrap()
-            }
-            x if MOD_FORMATS.get().unwrap().contains(&x.to_string()) => { // convert the tracker music to a tmp wav file.
-                // this is TEMPORARY until the openmpt crate starts working again
-                let mut cmd = Command::new("openmpt123"); // start making a new `openmpt123` command to run in terminal
-                cmd.args([path.to_str().unwrap(),"-o","/tmp/openmpt_convert.wav", "--force"]); // convert the selected tracker music file and put it at /tmp/openmpt_convert.wav, and replace it if it allready exists
-                cmd.stdout(Stdio::null()); // supress stdout 
-                let _ = cmd.spawn().unwrap().wait(); // run the command
-                StaticSoundData::from_file("/tmp/openmpt_convert.wav", StaticSoundSettings::default()).unwrap() // load wav file
-                
-                // the INTENDED method. but the openmpt crate is broken (does not fill buffers correctly)
-                //let mut file = File::open(path).unwrap();
-                //let module = Module::create(&mut file, Logger::None, &[]).unwrap();
-                //StreamingSoundData::from_decoder(ModDecoder::new(module), StreamingSoundSettings::default())
-            }
-            _ => {
-                println!("unsupported format '{}' file {}. SKIPPING",ext,path.to_str().unwrap_or("!!failed to unwrap path as str!!"));
-                return; // it failed to play song so we skip to next song
-            }
-        };
-
-        //set metadata's duration for the song
-        meta.duration = Some(sound.duration());
-
-        //create a new static sound handle for the song
+        //create a new streaming sound handle for the song
         let hand = self.manager.play(sound).unwrap();
         //set the handle for audio
         self.handle = Some(hand);
 
-        let _ = self.controls.set_metadata(meta); //set media metadata
-        let _ = self.controls.set_playback(souvlaki::MediaPlayback::Playing { progress: None }); //play the song (with no progress since we have not started)
-        println!("song is playing"); // notify user via text that song has started
-        
+        self.announce(path);
+    }
+    /// opens the next song and schedules it to start on the sample the current song ends on. so there is no gap between them.
+    /// with crossfade on it starts that much earlier instead, fading in while the current song fades out
+    fn preload_next(&mut self) {
+        if self.preloaded.is_some() || self.handle.is_none() {
+            return; // allready have one (or there is nothing to line it up behind)
+        }
+        while let Some(song) = self.next_song() {
+            let Some(decoder) = Self::open_song(&song) else { continue };
+            let tags = Self::tags_for(&song, &decoder);
+            let gain = self.gain_for(&song, &tags);
+            let fade_out = decoder.fade_out();
+            let tracker = decoder.tracker();
+            let fade = self.fade_into(&song);
+            // work out what clock tick the current song ends on (done after opening the file since that can take a bit)
+            let remaining = (self.duration.as_secs_f64() - self.handle.as_ref().unwrap().position() - fade.as_secs_f64()).max(0.0);
+            let start = self.clock.time() + (remaining * *OUTPUT_SAMPLE_RATE.get().unwrap() as f64).round() as u64;
+            let mut settings = StreamingSoundSettings::new().start_time(start).volume(Volume::Decibels(gain));
+            if !fade.is_zero() {
+                let tween = Tween { start_time: start.into(), duration: fade, ..Default::default() };
+                settings = settings.fade_in_tween(tween);
+                // fade the current song out over the same stretch. it reaches silence right as it ends
+                let _ = self.handle.as_mut().unwrap().set_volume(Volume::Amplitude(0.0), tween);
+            }
+            let sound = StreamingSoundData::from_decoder(decoder, settings);
+            let duration = sound.duration();
+            let handle = self.manager.play(sound).unwrap();
+            self.preloaded = Some(Preloaded { song, handle, duration, gain, fade_out, tracker, tags });
+            return;
+        }
+    }
+    /// throws away the preloaded song (if any) and puts it back at the front of the queue
+    /// needs to happen whenever the current song's end moves (seeking, skipping, etc)
+    fn cancel_preload(&mut self) {
+        if let Some(mut preloaded) = self.preloaded.take() {
+            let _ = preloaded.handle.stop(Tween::default());
+            self.upcoming.push_front(preloaded.song);
+            // undo the crossfade fade out that was scheduled on the current song
+            if let Some(handle) = self.handle.as_mut() {
+                let _ = handle.set_volume(Volume::Decibels(self.gain), Tween::default());
+            }
+            self.fading = false; // that undid the fade out too, if it had started
+        }
     }
-    /// pushes a specified PathBuf to the front of lookback. this voids a old value if the len is == capacity
-    fn push_song_to_lookback(&mut self, song: PathBuf) {
+    /// starts fading the current song out once it gets near its end, for songs that fade out (see `moddecoder.rs`).
+    /// called every tick, so it follows seeking and pausing without having to schedule anything ahead
+    fn update_fade_out(&mut self) {
+        let (Some(fade), Some(handle)) = (self.fade_out, self.handle.as_mut()) else { return };
+        let position = handle.position();
+        let start = (self.duration.saturating_sub(fade)).as_secs_f64();
+        if position >= start && !self.fading {
+            let left = (self.duration.as_secs_f64() - position).max(0.0);
+            let _ = handle.set_volume(Volume::Amplitude(0.0), Tween { duration: Duration::from_secs_f64(left), ..Default::default() });
+            self.fading = true;
+        } else if position < start && self.fading {
+            // seeked back before the fade
+            let _ = handle.set_volume(Volume::Decibels(self.gain), Tween::default());
+            self.fading = false;
+        }
+    }
+    /// makes the preloaded song the current song. it has allready started playing by the time this is called.
+    /// returns false if there was no preloaded song
+    fn promote_preloaded(&mut self) -> bool {
+        let Some(preloaded) = self.preloaded.take() else { return false };
+        self.handle = Some(preloaded.handle);
+        self.duration = preloaded.duration;
+        self.gain = preloaded.gain;
+        (self.fade_out, self.fading) = (preloaded.fade_out, false);
+        self.tracker = preloaded.tracker;
+        self.tags = preloaded.tags;
+        //push the song to loopback so the back button works
+        self.announce(&preloaded.song.path);
+        self.push_song_to_lookback(preloaded.song);
+        true
+    }
+    /// pauses or resumes the current song
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // the preloaded song too, it may be mid crossfade
+        for handle in self.handle.iter_mut().chain(self.preloaded.iter_mut().map(|p| &mut p.handle)) {
+            let _ = if paused {
+                handle.pause(Tween::default())//pause
+            } else {
+                handle.resume(Tween::default())//unpause
+            };
+        }
+        // the clock is paused too so the preloaded song does not start while we are paused
+        let _ = if paused { self.clock.pause() } else { self.clock.start() };
+    }
+    /// mutes or unmutes a channel (from 1) of the current tracker module, or plays only that channel if `solo`
+    fn toggle_channel(&mut self, channel: usize, solo: bool) -> Result<(), String> {
+        let tracker = self.tracker.as_ref().ok_or("the current song is not a tracker module")?;
+        let mut tracker = tracker.lock().unwrap();
+        let done = match channel.checked_sub(1) {
+            Some(i) if solo => tracker.toggle_solo(i),
+            Some(i) => tracker.toggle_mute(i),
+            None => false,
+        };
+        if !done {
+            return Err(format!("there is no channel {channel} (it has {})", tracker.muted.len()));
+        }
+        Ok(())
+    }
+    /// unmutes every channel of the current tracker module
+    fn unmute_channels(&mut self) {
+        if let Some(tracker) = &self.tracker {
+            tracker.lock().unwrap().muted.fill(false);
+        }
+    }
+    /// seeks to a specific point in the current song (in seconds)
+    fn seek_to(&mut self, position: f64) {
+        self.cancel_preload(); // the song will end at a different time now
+        let _ = self.handle.as_mut().map(|h| h.seek_to(position));
+    }
+    /// seeks foward (or backward if negative) in the current song by a number of seconds
+    fn seek_by(&mut self, amount: f64) {
+        self.cancel_preload(); // the song will end at a different time now
+        let _ = self.handle.as_mut().map(|h| h.seek_by(amount));
+    }
+    /// does what a media button asked for. the terminal UI sends its keys through here too
+    fn handle_event(&mut self, event: MediaControlEvent) {
+        match event {
+            MediaControlEvent::Next => self.play_next_song(),//skipping song
+            MediaControlEvent::Pause => self.set_paused(true), //pause it
+            MediaControlEvent::Play => self.set_paused(false), //unpause it
+            MediaControlEvent::Toggle => {
+                let rg = self.paused;//are we paused?
+                self.set_paused(!rg);
+            },
+            MediaControlEvent::Quit | MediaControlEvent::Stop => self.quit(), //quit the program
+            MediaControlEvent::Previous => {self.do_the_previous_one()} //go back 1 song
+            MediaControlEvent::SetPosition(pos) => { //seek to specific point in song
+                self.seek_to(pos.0.as_secs_f64());
+            }
+            MediaControlEvent::Seek(dir) => { //seed by a specified direction 10 seconds
+                self.seek_by(match dir {
+                    SeekDirection::Forward => 10.0,
+                    SeekDirection::Backward => -10.0
+                });
+            }
+            MediaControlEvent::SeekBy(dir, dur) => { //seeks by a specified number of seconds foward/bacl
+                self.seek_by(
+                    match dir {
+                        SeekDirection::Forward => 1.0,
+                        SeekDirection::Backward => -1.0
+                    } * dur.as_secs_f64()
+                );
+            }
+            x => println!("Event not yet implemented {:?}",x) //catch all for other un-implemented buttons (I have not found any)
+        }
+    }
+    /// sets the volume of everything (as a factor, 1.0 is full volume)
+    fn set_volume(&mut self, volume: f64) {
+        self.volume = volume.clamp(0.0, 1.0);
+        let _ = self.manager.main_track().set_volume(Volume::Amplitude(self.volume), Tween::default());
+    }
+    /// shuffles the songs that have not played yet
+    fn shuffle_upcoming(&mut self) {
+        self.cancel_preload(); // so the preloaded song gets shuffled in too
+        // the rest of a group that is playing right now has to stay right after it
+        let rest_of_group = self.upcoming.iter().take_while(|song| song.grouped).count();
+        let mut songs: Vec<Song> = self.upcoming.drain(rest_of_group..).collect();
+        shuffle::shuffle(&mut songs, self.shuffle, &mut self.rng);
+        if self.no_repeat {
+            shuffle::spread(&mut songs, &self.recent_songs(), &mut self.rng);
+        }
+        self.upcoming.extend(songs);
+    }
+    /// the songs that played lately, newest first. the lookback then the play log
+    fn recent_songs(&self) -> Vec<PathBuf> {
+        self.lookback.iter().map(|song| song.path.clone()).chain(session::plays()).collect()
+    }
+    /// the current song and everything after it
+    fn queued_songs(&self) -> Vec<Song> {
+        self.lookback.front().into_iter()
+            .chain(self.preloaded.iter().map(|p| &p.song))
+            .chain(self.upcoming.iter())
+            .cloned().collect()
+    }
+    /// saves the current song and everything after it to a M3U8 playlist
+    fn save_queue(&self, path: &Path) -> std::io::Result<usize> {
+        let songs = self.queued_songs();
+        playlist::write_m3u(path, &songs)?;
+        Ok(songs.len())
+    }
+    /// saves the session so `--resume` can pick it back up
+    fn save_session(&self) {
+        if let Err(e) = session::save(self) {
+            println!("could not save the session: {e}");
+        }
+    }
+    /// quits the program (putting the terminal back how we found it first)
+    fn quit(&mut self) -> ! {
+        self.save_session();
+        tui::restore();
+        #[cfg(unix)]
+        control::cleanup();
+        exit(0)
+    }
+    /// pushes a specified Song to the front of lookback. this voids a old value if the len is == capacity
+    fn push_song_to_lookback(&mut self, song: Song) {
         if self.lookback.len() == self.lookback.capacity() {
             let _ = self.lookback.pop_back(); //we know it is at capacity. this makes it so that we clear the last index and prevent it from crashing due to being over full
         }
@@ -169,6 +516,8 @@ impl Status {
 
     /// plays the song at the front of the lookback...
     fn do_the_previous_one(&mut self) {
+        // put the preloaded song back first so it ends up after the songs we are about to push
+        self.cancel_preload();
         // we pop one from the lookback (the current song)
         if let Some(song) = self.lookback.pop_front() {
             self.upcoming.push_front(song);
@@ -184,43 +533,207 @@ impl Status {
 
 #[derive(Parser, Debug)]
 #[command(author = "[redacted]", version = "v1", about = "command line music player", long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// whether or not to shuffle the audio before playing/when playlist is empty
-    #[arg(short, long, help = "sets whether or not to shuffle the music list")]
-    shuffle: bool,
+    /// whether or not to shuffle the audio before playing/when playlist is empty (and what to keep together)
+    #[arg(short, long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "group", help = "sets whether or not to shuffle the music list (by track, album or group)")]
+    shuffle: Option<ShuffleMode>,
+
+    /// whether to keep recently played songs (and songs by the same artist) apart when shuffling
+    #[arg(long, requires = "shuffle", help = "when shuffling, play recently played songs last and spread out songs by the same artist or folder")]
+    no_repeat: bool,
+
+    /// the seed for shuffling, so the same order can be had again
+    #[arg(long, help = "seed the shuffle so the order can be repeated (the seed used is printed)")]
+    seed: Option<u64>,
 
     /// whether or not to loop the playlist/song when it is empty/over
     #[arg(short, long, help = "sets looping of the music when all songs have been played")]
     looping: bool,
+
+    /// how many seconds to crossfade between songs for (songs in a `@` line never crossfade)
+    #[arg(short, long, default_value_t = 0.0, help = "crossfade between songs for this many seconds (0 turns it off)")]
+    crossfade: f64,
     
+    /// which replaygain tags to even out the volume with
+    #[arg(long, value_enum, default_value_t = ReplayGainMode::Off, help = "even out the volume of songs using their replaygain tags")]
+    replaygain: ReplayGainMode,
+
+    /// whether to measure the loudness of songs that have no replaygain tags
+    #[arg(long, help = "measure the EBU R128 loudness of songs with no replaygain tags (only with --replaygain)")]
+    analyze_loudness: bool,
+
+    /// whether to show the terminal UI
+    #[arg(short, long, help = "show a terminal UI with the queue and keyboard controls")]
+    tui: bool,
+
+    /// whether to queue the files right after the current song when they are handed to a player that is allready running
+    #[arg(short, long, help = "if a player is allready running, play the files next instead of adding them to the end of its queue")]
+    next: bool,
+
+    /// whether to start a player even if one is already running
+    #[arg(long, help = "start a new player even if one is already running")]
+    new_instance: bool,
+
+    /// whether to carry on from where the last run stopped
+    #[arg(short, long, help = "carry on with the queue, song and position from when the player last quit")]
+    resume: bool,
+
+    /// only play songs that match this (see `query.rs`)
+    #[arg(short, long, help = "only play songs that match a query, e.g. 'artist:\"Toby Fox\" AND duration<180'. with no files it searches the whole library index")]
+    query: Option<String>,
+
+    /// globs that files found in folders have to match
+    #[arg(long, help = "only play files in folders that match this glob (can be given more than once)")]
+    include: Vec<String>,
+
+    /// globs for files in folders to leave out
+    #[arg(long, help = "leave out files in folders that match this glob, e.g. '**/Scans/**' (can be given more than once)")]
+    exclude: Vec<String>,
+
+    /// the subsong of tracker modules to play
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-1..), help = "which subsong of tracker modules to play, counting from 0 (-1 plays all of them one after the other)")]
+    subsong: i32,
+
+    /// whether to queue every subsong of a tracker module on its own
+    #[arg(long, conflicts_with = "subsong", help = "queue every subsong of tracker modules as a song of its own")]
+    every_subsong: bool,
+
+    /// how many extra times to play tracker modules
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(i32).range(0..), help = "play tracker modules this many extra times")]
+    repeat_count: i32,
+
+    /// whether to let tracker modules loop forever and fade them out after a while
+    #[arg(long, conflicts_with = "repeat_count", help = "let tracker modules loop forever, then fade them out after --endless-loops loops or --endless-minutes minutes")]
+    endless: bool,
+
+    /// how many times through an endless module plays before fading out
+    #[arg(long, requires = "endless", help = "fade endless tracker modules out after this many loops (2 if --endless-minutes is not given either)")]
+    endless_loops: Option<u32>,
+
+    /// how many minutes an endless module plays before fading out
+    #[arg(long, requires = "endless", help = "fade endless tracker modules out after this many minutes")]
+    endless_minutes: Option<f64>,
+
+    /// how long endless modules take to fade out
+    #[arg(long, default_value_t = 10.0, help = "how many seconds endless tracker modules take to fade out")]
+    endless_fade: f64,
+
+    /// the stereo separation of tracker modules, in percent
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(i32).range(0..=200), help = "stereo separation of tracker modules in percent (0 is mono, 200 is extra wide)")]
+    stereo_separation: i32,
+
+    /// how tracker modules are resampled
+    #[arg(long, value_enum, default_value_t = Interpolation::Default, help = "the interpolation filter for tracker modules")]
+    interpolation: Interpolation,
+
+    /// how strongly volume changes in tracker modules are smoothed
+    #[arg(long, default_value_t = -1, allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-1..=10), help = "volume ramping strength for tracker modules (0 is off, up to 10, -1 leaves it to libopenmpt)")]
+    volume_ramping: i32,
+
     /// all the songs/playlist to play
-    #[arg(required(true))]
+    #[arg(required_unless_present_any = ["resume", "query"])]
     files: Vec<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// send a command to the player that is allready running (see `control.rs` for the list)
+    #[cfg(unix)]
+    Ctl {
+        /// the command and its arguments, e.g. `seek +10` or `enqueue ~/music`
+        #[arg(required(true), trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+/// sends a command to the running player over the control socket, prints the answer and exits
+#[cfg(unix)]
+fn run_ctl(mut command: Vec<String>) -> ! {
+    // the player does not know what folder we are in, so give it a full path
+    if ["enqueue", "playnext", "save"].contains(&command[0].as_str()) && command.len() > 1 {
+        let path = match control::absolute(Path::new(&command[1..].join(" "))) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("bad group: {e}");
+                exit(1)
+            }
+        };
+        command = vec![command[0].clone(), path.to_string_lossy().into_owned()];
+    }
+    match control::send(&command.join(" ")) {
+        Ok(Ok(lines)) => {
+            for line in lines {
+                println!("{line}");
+            }
+            exit(0)
+        }
+        Ok(Err(e)) => {
+            eprintln!("error: {e}");
+            exit(1)
+        }
+        Err(e) => {
+            eprintln!("could not talk to the player at {:?}: {e}", control::socket_path());
+            exit(1)
+        }
+    }
 }
 
 /// a once lock to hold a mutex of our status so we can refrence and init it later
 static GLOBAL_STATE: OnceLock<Mutex<Status>> = OnceLock::new();
 
-/// I *would* do this at compile time. but it can change from platform to platform.
-static MOD_FORMATS: OnceLock<Vec<String>> = OnceLock::new();
+/// the sample rate of the sound card. tracker music is rendered at this rate so kira does not have to resample it
+static OUTPUT_SAMPLE_RATE: OnceLock<u32> = OnceLock::new();
+
+/// asks cpal (the same way kira does) what sample rate the default output device runs at. falls back to 48khz
+fn output_sample_rate() -> u32 {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|device| device.default_output_config().ok())
+        .map_or(48000, |config| config.sample_rate().0)
+}
 
 /// this function gets all songs withing a folder. or the file it's self (recursive)
-fn get_songs(file_or_path: &Path) -> Vec<PathBuf> {
+fn get_songs(file_or_path: &Path) -> Vec<Song> {
+    if file_or_path.to_string_lossy().starts_with('@') {
+        // a group given straight on the command line (or over the control socket)
+        return match group::load(&file_or_path.to_string_lossy(), 1, Path::new("")) {
+            Ok(song) => vec![song],
+            Err(e) => {
+                println!("bad group {file_or_path:?}: {e}");
+                vec![]
+            }
+        };
+    }
     if file_or_path.is_dir() {
         // if it is a folder we need to get all songs within said folder... recursively
+        // the library index knows what is in it (and only looks again at the parts that changed)
+        let files = {
+            let mut library = library::library().lock().unwrap();
+            let files = library.files_in(file_or_path);
+            if let Err(e) = library.save() {
+                println!("could not save the library index: {e}");
+            }
+            files
+        }; // let go of the index before going into the files, they can be playlists that point at more folders
         // create a array to hold all songs within this folder.
         let mut q = Vec::new();
-        // now we iterate over all files. if it was able to read the folder.
-        if let Ok(entries) = fs::read_dir(file_or_path) {
-            // flatten the directory into a entry
-            for entry in entries.flatten() {
-                if entry.path().exists() {
-                    q.extend(get_songs(&entry.path()));
-                }
-            }
+        for file in files.into_iter().filter(|file| filter::keep(file)) {
+            q.extend(get_songs(&file));
         }
+        // songs the playlists said nothing about get their tags from the index, so the queue can show them
+        let library = library::library().lock().unwrap();
+        for song in q.iter_mut().filter(|song| song.hint.is_none()) {
+            song.hint = library.entry(&song.path).map(|entry| entry.hint());
+        }
+        // a album ripped to one file with a CUE sheet next to it gets played as its tracks, not as the whole file as well
+        let split: HashSet<PathBuf> = q.iter().filter(|s| s.region.is_some()).map(|s| s.path.clone()).collect();
+        q.retain(|s| s.region.is_some() || !split.contains(&s.path));
         // sort alphabetically
-        q.sort_by(|a, b| b.cmp(a));
+        q.sort_by(|a, b| b.path.cmp(&a.path));
         q
     } else {
         // the path specified is a single file
@@ -228,19 +741,33 @@ fn get_songs(file_or_path: &Path) -> Vec<PathBuf> {
         println!("{:?}",file_or_path);
         // we get the extension.
         let ext = file_or_path.extension().unwrap_or(OsStr::new("")).to_str().unwrap();
-        match ext {
-            "m3u" => { //playlist format so we add each line to the list
-                let contents = fs::read_to_string(file_or_path).unwrap_or_default();
-                let mut lines: Vec<&str> = contents.lines().collect();
-                lines.reverse(); // the iterator reverses it. so to keep it in order. we reverse the lines here.
+        match playlist::read(file_or_path, ext) {
+            Some(mut entries) => { //playlist format so we add each entry to the list
+                entries.reverse(); // the iterator reverses it. so to keep it in order. we reverse the entries here.
 
                 let mut final_songs = Vec::new(); // create a final of list of songs
-                for l in lines {
-                    final_songs.extend(get_songs(&PathBuf::from_str(l).unwrap()));
+                for entry in entries {
+                    if entry.region.is_some() || entry.subsong.is_some() || !entry.members.is_empty() {
+                        final_songs.push(entry); // a CUE track, a subsong or a group. allready points at the audio files
+                        continue;
+                    }
+                    // the playlist's hint goes on whatever the entry turns out to be (unless it is a folder or playlist itself)
+                    final_songs.extend(get_songs(&entry.path).into_iter().map(|song| Song { hint: song.hint.or(entry.hint.clone()), ..song }));
                 }
                 final_songs
             }
-            _ => vec![file_or_path.into()], // it is not a playlist so we just pass the file directly
+            None => {
+                // a file with more than one song in it (a tracker module with subsongs) can go in as each of them
+                let subsongs = moddecoder::render_settings().every_subsong
+                    .then(|| format::find(file_or_path)?.subsongs(file_or_path))
+                    .flatten();
+                match subsongs {
+                    Some(count) => (0..count).rev()
+                        .map(|subsong| Song { subsong: Some(subsong), ..file_or_path.to_path_buf().into() })
+                        .collect(),
+                    None => vec![file_or_path.to_path_buf().into()], // it is not a playlist so we just pass the file directly
+                }
+            }
         }
     }
 }
@@ -265,9 +792,79 @@ fn update_playback(state: &mut Status) {
     }
 }
 
+/// adds the songs from the files (and the query) we were given to the end of the queue, shuffled if we shuffle
+fn fill_queue(args: &Args, query: Option<&Query>, state: &mut Status) {
+    let mut queue = vec![];
+    for path in &args.files {
+        queue.append(&mut get_songs(path));
+    }
+    if let Some(query) = query {
+        if args.files.is_empty() {
+            // search everything in the library index. backwards like get_songs hands them back
+            let paths = library::library().lock().unwrap().paths();
+            queue.extend(paths.into_iter().rev().filter(|path| filter::keep(path)).flat_map(|path| get_songs(&path)));
+        }
+        query::filter(&mut queue, query);
+        println!("{} songs match the query", queue.len());
+    }
+    filter::report();
+    queue.dedup(); // remove duplicate songs... (note: may remove this later)
+    queue.reverse();
+    if let Some(mode) = args.shuffle {
+        print!("Shuffling...");
+        shuffle::shuffle(&mut queue, mode, &mut state.rng);
+        if args.no_repeat {
+            let recent = state.recent_songs();
+            shuffle::spread(&mut queue, &recent, &mut state.rng);
+        }
+        println!(" Done!");
+    }
+    state.upcoming.extend(queue);
+}
+
 fn main() {
-    let args = Args::parse(); // parse args
-    let _ = MOD_FORMATS.set(get_supported_extensions().split(';').map(|x| x.to_string()).collect()); // init the MOD_FORMATS
+    let matches = Args::command().get_matches(); // parse args. we keep the matches to see which ones were given
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    match args.command {
+        #[cfg(unix)]
+        Some(Command::Ctl { command }) => run_ctl(command),
+        None => {}
+    }
+    // if a player is already running it gets our files and we are done
+    #[cfg(unix)]
+    if !args.new_instance {
+        // everything but the files (and how to hand them over) only means something to a new player
+        let flags: Vec<String> = Args::command().get_arguments()
+            .filter(|arg| !["files", "next", "new_instance"].contains(&arg.get_id().as_str()))
+            .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+            .filter_map(|arg| arg.get_long().map(|long| format!("--{long}")))
+            .collect();
+        match control::forward(&args.files, args.next, &flags) {
+            Ok(true) => exit(0),
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1)
+            }
+        }
+    }
+    // the formats we can play. symphonia's first, then everything libopenmpt knows
+    format::register(filedecoder::FileFormat::new());
+    format::register(moddecoder::TrackerFormat::new());
+    let _ = OUTPUT_SAMPLE_RATE.set(output_sample_rate()); // init the OUTPUT_SAMPLE_RATE
+    let _ = moddecoder::RENDER_SETTINGS.set(RenderSettings {
+        subsong: args.subsong,
+        every_subsong: args.every_subsong,
+        repeat_count: args.repeat_count,
+        endless: args.endless.then(|| Endless {
+            loops: args.endless_loops.or(args.endless_minutes.is_none().then_some(2)),
+            minutes: args.endless_minutes,
+            fade: Duration::from_secs_f64(args.endless_fade.max(0.0)),
+        }),
+        stereo_separation: args.stereo_separation,
+        interpolation: args.interpolation,
+        volume_ramping: args.volume_ramping,
+    });
 
     // souvlaki stuff... I just copied from the docs
     //#[cfg(not(target_os = "windows"))]
@@ -299,46 +896,7 @@ fn main() {
         .attach(|event| {
             //media button handler
             let mut state = GLOBAL_STATE.get().unwrap().lock().unwrap(); //lock the state so we can change it
-            match event {
-                MediaControlEvent::Next => state.play_next_song(),//skipping song
-                MediaControlEvent::Pause => {
-                    state.paused = true; //pause it
-                    let _ = state.handle.as_mut().map(|h| h.pause(Tween::default()));
-                },
-                MediaControlEvent::Play => {
-                    state.paused = false; //unpause it
-                    let _ = state.handle.as_mut().map(|h| h.resume(Tween::default()));
-                },
-                MediaControlEvent::Toggle => {
-                    let rg = state.paused;//are we paused?
-                    if let Some(handle) = state.handle.as_mut() { let _ = if rg {
-                            handle.resume(Tween::default())//unpause 
-                        } else {
-                            handle.pause(Tween::default())//pause
-                        }; }
-                    state.paused = !rg;
-                },
-                MediaControlEvent::Quit | MediaControlEvent::Stop => {exit(0)}, //quit the program
-                MediaControlEvent::Previous => {state.do_the_previous_one()} //go back 1 song
-                MediaControlEvent::SetPosition(pos) => { //seek to specific point in song
-                    state.handle.as_mut().map(|h| h.seek_to(pos.0.as_secs_f64()));
-                }
-                MediaControlEvent::Seek(dir) => { //seed by a specified direction 10 seconds
-                    state.handle.as_mut().map(|h| h.seek_by(match dir {
-                        SeekDirection::Forward => 10.0,
-                        SeekDirection::Backward => -10.0
-                    }));
-                }
-                MediaControlEvent::SeekBy(dir, dur) => { //seeks by a specified number of seconds foward/bacl
-                    state.handle.as_mut().map(|h| h.seek_by(
-                        match dir {
-                            SeekDirection::Forward => 1.0,
-                            SeekDirection::Backward => -1.0
-                        } * dur.as_secs_f64()
-                    ));
-                }
-                x => println!("Event not yet implemented {:?}",x) //catch all for other un-implemented buttons (I have not found any)
-            }
+            state.handle_event(event);
             update_playback(&mut state);
         })
         .unwrap();
@@ -353,7 +911,21 @@ fn main() {
         })
         .unwrap();
     
-    let manager = AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()).unwrap();
+    let mut manager = AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()).unwrap();
+    // a clock that ticks once per output sample so we can line songs up back to back
+    let clock = manager.add_clock(ClockSpeed::TicksPerSecond(*OUTPUT_SAMPLE_RATE.get().unwrap() as f64)).unwrap();
+    clock.start().unwrap();
+
+    filter::set(filter::Filter { include: args.include.clone(), exclude: args.exclude.clone() });
+    let query = args.query.as_deref().map(|text| Query::parse(text).unwrap_or_else(|e| {
+        eprintln!("bad query: {e}");
+        exit(1)
+    }));
+
+    let seed = args.seed.unwrap_or_else(|| thread_rng().gen());
+    if args.shuffle.is_some() {
+        println!("shuffle seed: {seed} (use --seed {seed} to get this order again)");
+    }
 
     #[cfg(debug_assertions)]
     println!("creating GLOBAL_STATE"); // setup the global state with all the instances created above.
@@ -363,21 +935,67 @@ fn main() {
         manager,
         upcoming: VecDeque::new(), 
         lookback: VecDeque::with_capacity(32),
-        handle: None
+        handle: None,
+        duration: Duration::ZERO,
+        preloaded: None,
+        clock,
+        crossfade: Duration::from_secs_f64(args.crossfade.max(0.0)),
+        gain: 0.0,
+        fade_out: None,
+        fading: false,
+        tracker: None,
+        tags: Tags::default(),
+        replaygain: args.replaygain,
+        analyze_loudness: args.analyze_loudness,
+        volume: 1.0,
+        shuffle: args.shuffle.unwrap_or(ShuffleMode::Group),
+        rng: StdRng::seed_from_u64(seed),
+        no_repeat: args.no_repeat,
     })).unwrap();
+
+    if args.tui {
+        tui::start();
+    }
+    #[cfg(unix)]
+    control::listen();
+
+    // pick up where the last run left off. the saved queue plays before the files we were given
+    let mut resume_position = None;
+    if args.resume {
+        let mut state = GLOBAL_STATE.get().unwrap().lock().unwrap();
+        match session::load() {
+            Some(session) => {
+                state.upcoming.extend(session.upcoming);
+                let capacity = state.lookback.capacity();
+                state.lookback.extend(session.lookback.into_iter().take(capacity - 1)); // room for the song we resume
+                resume_position = Some(session.position);
+            }
+            None => println!("no saved session to resume"),
+        }
+        if !args.files.is_empty() || query.is_some() {
+            fill_queue(&args, query.as_ref(), &mut state);
+        }
+    }
+    let mut last_save = std::time::Instant::now();
   
     loop {
         let mut state = GLOBAL_STATE.get().unwrap().lock().unwrap(); // wait to lock the global state (thread safe waiting for ownership)
-        let stopped = !state.handle.as_ref().map_or(true, |x| x.state() == PlaybackState::Playing || x.state() == PlaybackState::Paused);
+        let mut stopped = !state.handle.as_ref().is_none_or(|x| x.state() == PlaybackState::Playing || x.state() == PlaybackState::Paused);
         // stopped is something dumb and I forgot why it works anymore. but it does so we dont question it
+        if stopped && state.promote_preloaded() {
+            // the song ended but the next one was lined up and has allready started. so nothing stopped really
+            stopped = false;
+        }
         if
             state.upcoming.is_empty() && stopped
             
         {
-            // the queue is empty and no audio is playing. let us refill it or exit the program
-            if args.looping {
+            // the queue is empty and no audio is playing. let us refill it or exit the program (a resumed session has
+            // nothing to refill from, so it stops)
+            if args.looping && (!args.files.is_empty() || query.is_some()) {
                 state.handle = None;
             } else {
+                state.save_session();
                 break
             }
         }
@@ -385,28 +1003,41 @@ fn main() {
         // refill queue
         if state.upcoming.is_empty() && state.handle.is_none() {
             println!("filling queue.");
-            let mut queue = vec![];
-            for path in &args.files {
-                queue.append(&mut get_songs(path));
-            }
-            queue.dedup(); // remove duplicate songs... (note: may remove this later)
-            queue.reverse();
-            if args.shuffle {
-                print!("Shuffling...");
-                queue.shuffle(&mut thread_rng());
-                println!(" Done!");
-            }
-            state.upcoming.append(&mut queue.into());
+            fill_queue(&args, query.as_ref(), &mut state);
             #[cfg(debug_assertions)]
-            println!("upcoming {:?}",state.upcoming)
+            println!("upcoming {:?}",state.upcoming);
+            if state.upcoming.is_empty() {
+                println!("nothing to play.");
+                break
+            }
         }
         if stopped || state.handle.is_none() {
             println!("playing");
             state.play_next_song();
+            if let Some(position) = resume_position.take() {
+                state.seek_to(position);
+            }
         } else {
+            // open the next song a few seconds early so it can start the moment this one ends
+            let remaining = state.duration.as_secs_f64() - state.handle.as_ref().map_or(0.0, |h| h.position());
+            if remaining < PRELOAD_SECONDS + state.crossfade.as_secs_f64() {
+                state.preload_next();
+            }
+            state.update_fade_out();
+            state.measure_upcoming();
             update_playback(&mut state);
         }
+        if args.tui {
+            tui::draw(&state);
+        }
+        if last_save.elapsed() > SESSION_SAVE_INTERVAL {
+            state.save_session();
+            last_save = std::time::Instant::now();
+        }
         drop(state); // release the lock before we sleep so other threads have 100ms to access it before we lock it again
         std::thread::sleep(std::time::Duration::from_millis(100));
     }
+    tui::restore();
+    #[cfg(unix)]
+    control::cleanup();
 }
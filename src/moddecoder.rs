@@ -1,46 +1,306 @@
-// UNUSED cause openmpt is dumb. see below comment for why
+// streams tracker music straight out of libopenmpt. no more rendering to a wav with openmpt123 first.
+// the old version of this was broken cause the openmpt crate writes into the *capacity* of the buffers
+// but never sets their length. so the vecs always looked empty. we set the length ourselves now.
 
-use std::ffi::c_float;
+use std::{ffi::c_float, fs::File, path::Path, sync::{Arc, Mutex, OnceLock}, time::Duration};
 
 use kira::{
     dsp::Frame,
     sound::{streaming::Decoder, FromFileError},
 };
-use openmpt::module::Module;
+use clap::ValueEnum;
+use openmpt::{info::get_supported_extensions, module::{metadata::MetadataKey, Logger, Module}};
 
+use crate::{
+    decoder::OpenError,
+    format::{Format, SongDecoder},
+    openmpt_ext::{probe_file_header, ExtModule},
+    tags::{non_empty, Tags},
+    OUTPUT_SAMPLE_RATE,
+};
+
+/// how many frames we ask libopenmpt for each time `decode` is called
+const CHUNK_SIZE: usize = 4096;
+
+/// the interpolation filter libopenmpt resamples with
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Interpolation {
+    /// whatever libopenmpt thinks is best
+    #[default]
+    Default,
+    /// no interpolation at all (crunchy, like the real hardware)
+    None,
+    /// linear
+    Linear,
+    /// cubic
+    Cubic,
+    /// 8 tap windowed sinc
+    Sinc,
+}
+
+impl Interpolation {
+    /// the filter length libopenmpt wants for this
+    fn filter_length(self) -> i32 {
+        match self {
+            Interpolation::Default => 0,
+            Interpolation::None => 1,
+            Interpolation::Linear => 2,
+            Interpolation::Cubic => 4,
+            Interpolation::Sinc => 8,
+        }
+    }
+}
+
+/// how long a module that loops forever plays before it fades out
+#[derive(Debug, Clone, Copy)]
+pub struct Endless {
+    /// stop after this many times through. `None` goes by `minutes` alone
+    pub loops: Option<u32>,
+    /// stop after this many minutes. if `loops` is set too, whichever comes first
+    pub minutes: Option<f64>,
+    /// how long the fade out at the end is
+    pub fade: Duration,
+}
+
+impl Endless {
+    /// how many frames to play before the fade starts, given how long one play through is
+    fn play_frames(&self, pass_frames: usize, sample_rate: u32) -> usize {
+        let by_loops = self.loops.map(|loops| pass_frames * loops as usize);
+        let by_minutes = self.minutes.map(|minutes| (minutes * 60.0 * sample_rate as f64) as usize);
+        match (by_loops, by_minutes) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b).unwrap_or(pass_frames),
+        }
+    }
+}
+
+/// how libopenmpt renders modules. comes from the command line
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    /// the subsong to play when a song does not say which. `-1` plays all of them one after the other
+    pub subsong: i32,
+    /// queue every subsong of a module on its own instead of just one
+    pub every_subsong: bool,
+    /// how many extra times to play a module
+    pub repeat_count: i32,
+    /// loop modules forever (in libopenmpt, so the loop points are right) and fade out after a while instead
+    pub endless: Option<Endless>,
+    /// stereo separation in percent (0 to 200)
+    pub stereo_separation: i32,
+    /// the interpolation filter
+    pub interpolation: Interpolation,
+    /// volume ramping strength (-1 is libopenmpt's default, 0 is off, up to 10)
+    pub volume_ramping: i32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings { subsong: 0, every_subsong: false, repeat_count: 0, endless: None, stereo_separation: 100, interpolation: Interpolation::Default, volume_ramping: -1 }
+    }
+}
+
+/// the render settings for every module we play
+pub static RENDER_SETTINGS: OnceLock<RenderSettings> = OnceLock::new();
+
+/// the render settings (the defaults if they were never set)
+pub fn render_settings() -> RenderSettings {
+    *RENDER_SETTINGS.get_or_init(RenderSettings::default)
+}
+
+/// loads a module from a file
+pub fn load_module(path: &Path) -> Option<Module> {
+    let mut file = File::open(path).ok()?;
+    Module::create(&mut file, Logger::None, &[]).ok()
+}
+
+/// tracker modules, in every format libopenmpt knows
+pub struct TrackerFormat {
+    extensions: Vec<String>,
+}
+
+impl TrackerFormat {
+    pub fn new() -> TrackerFormat {
+        TrackerFormat { extensions: get_supported_extensions().split(';').map(|x| x.to_lowercase()).collect() }
+    }
+}
+
+impl Format for TrackerFormat {
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn probe(&self, header: &[u8], file_size: u64) -> bool {
+        probe_file_header(header, file_size)
+    }
+
+    fn open(&self, path: &Path, subsong: Option<i32>) -> Result<Box<dyn SongDecoder>, OpenError> {
+        // stream the tracker music straight from libopenmpt
+        let module = ExtModule::load(path).map_err(|e| OpenError::File(e.into()))?.ok_or(OpenError::Module)?;
+        Ok(Box::new(ModDecoder::new(module, *OUTPUT_SAMPLE_RATE.get().unwrap(), subsong)))
+    }
+
+    fn subsongs(&self, path: &Path) -> Option<i32> {
+        Some(load_module(path).map_or(1, |mut module| module.get_num_subsongs().max(1)))
+    }
+}
+
+/// what a module is up to, and which of its channels are muted. the decoder lives on kira's thread, so it fills this in
+/// every time it renders and the player reads it from there. it is a chunk or so ahead of what can be heard
+#[derive(Debug, Clone, Default)]
+pub struct TrackerState {
+    /// the position in the order list
+    pub order: i32,
+    /// how long the order list is
+    pub num_orders: i32,
+    /// the pattern being played
+    pub pattern: i32,
+    /// the row of the pattern
+    pub row: i32,
+    /// ticks per row
+    pub speed: i32,
+    /// the tempo, in BPM
+    pub tempo: i32,
+    /// how loud each channel is (left, right), from 0 to 1
+    pub vu: Vec<(f32, f32)>,
+    /// which channels the player wants muted. the decoder mutes them before it renders the next chunk
+    pub muted: Vec<bool>,
+}
+
+impl TrackerState {
+    /// mutes or unmutes a channel (from 0). returns false if there is no such channel
+    pub fn toggle_mute(&mut self, channel: usize) -> bool {
+        let Some(muted) = self.muted.get_mut(channel) else { return false };
+        *muted = !*muted;
+        true
+    }
+
+    /// plays only `channel`. if it is allready the only one playing, everything plays again
+    pub fn toggle_solo(&mut self, channel: usize) -> bool {
+        if channel >= self.muted.len() {
+            return false;
+        }
+        let soloed = self.muted.iter().enumerate().all(|(i, &muted)| muted == (i != channel));
+        for (i, muted) in self.muted.iter_mut().enumerate() {
+            *muted = !soloed && i != channel;
+        }
+        true
+    }
+}
+
+/// a kira decoder that renders a tracker module on demand
 pub struct ModDecoder {
-    frames: Vec<Frame>,
-    pos: usize,
+    /// the libopenmpt module we render from
+    module: ExtModule,
+    /// the sample rate we render at (should be the sample rate of the output device so kira does not have to resample)
+    sample_rate: u32,
+    /// the total number of frames in the song (worked out from libopenmpt's duration)
+    num_frames: usize,
+    /// how many frames one play through is (`num_frames` counts the repeats too)
+    pass_frames: usize,
+    /// how many extra times the module plays (-1 is forever)
+    repeat_count: i32,
+    /// how long the fade out at the end is, if the module loops forever
+    fade_out: Option<Duration>,
+    /// left channel buffer that libopenmpt renders into
+    left: Vec<c_float>,
+    /// right channel buffer that libopenmpt renders into
+    right: Vec<c_float>,
+    /// the title/artist/message stored in the module
+    tags: Tags,
+    /// what the module is up to, shared with the player
+    tracker: Arc<Mutex<TrackerState>>,
+    /// the channels we have muted in libopenmpt
+    muted: Vec<bool>,
 }
 
 unsafe impl Send for ModDecoder {} // tell the compiler that we are safe to `Send` across threads
 
 impl ModDecoder {
-    pub fn new(mut module: Module) -> ModDecoder {
-        println!("decoding duration: {}",module.get_duration_seconds());
-        let mut frames = vec![];
-        let mut bytes_poped = 1;
-        let mut left: Vec<c_float> = Vec::with_capacity(22050);
-        let mut right: Vec<c_float> = Vec::with_capacity(22050);
-        while bytes_poped != 0 {
-            // okay so the bytes_poped gets set correctly. but at the same time no values are pushed into the left/right audio buffers
-            // either this is a OpenMPT problem or smth about Rust C interop. IDK
-            bytes_poped = module.read_float_stereo(22050, &mut left, &mut right);
-            println!("wrote {} bytes",bytes_poped);
-            println!("{left:?} {right:?}");
-            right.reverse();
-            for val in left.iter() {
-                println!("frame push");
-                frames.push(
-                    Frame {
-                        left: val.clone(),
-                        right: right.pop().unwrap() // they should hopefully be the same size.
-                    }
-                )
-            }
-            left.clear()
+    /// creates a new decoder that renders `module` at `sample_rate`. plays `subsong`, or the one from the render settings
+    pub fn new(mut module: ExtModule, sample_rate: u32, subsong: Option<i32>) -> ModDecoder {
+        let settings = render_settings();
+        let subsong = subsong.unwrap_or(settings.subsong);
+        module.select_subsong(subsong);
+        let repeat_count = if settings.endless.is_some() { -1 } else { settings.repeat_count };
+        module.set_repeat_count(repeat_count);
+        module.set_render_stereo_separation(settings.stereo_separation);
+        module.set_render_interpolation_filter_length(settings.interpolation.filter_length());
+        module.set_render_volume_ramping(settings.volume_ramping);
+        // the duration is of one play through, so the repeats have to be added on
+        let pass_frames = (module.get_duration_seconds() * sample_rate as f64).ceil() as usize;
+        let num_frames = match settings.endless {
+            // it never ends by its self, so the length is what we decide to play of it plus the fade out
+            Some(endless) => endless.play_frames(pass_frames, sample_rate) + (endless.fade.as_secs_f64() * sample_rate as f64) as usize,
+            None => pass_frames * (repeat_count + 1) as usize,
         };
-        ModDecoder { frames, pos: 0 }
+        let mut title = module.get_metadata(MetadataKey::ModuleTitle).and_then(non_empty);
+        // modules with more than one subsong often name them. put the name after the title
+        if subsong >= 0 && module.get_num_subsongs() > 1 {
+            let name = non_empty(module.get_subsong_name(subsong)).unwrap_or_else(|| format!("subsong {}", subsong + 1));
+            title = Some(title.map_or(name.clone(), |title| format!("{title} ({name})")));
+        }
+        let tags = Tags {
+            title,
+            artist: module.get_metadata(MetadataKey::ModuleArtist).and_then(non_empty),
+            comment: module.get_metadata(MetadataKey::SongMessage).and_then(non_empty),
+            ..Default::default()
+        };
+        let channels = module.get_num_channels().max(0) as usize;
+        let tracker = TrackerState { num_orders: module.get_num_orders(), muted: vec![false; channels], ..Default::default() };
+        ModDecoder {
+            module,
+            sample_rate,
+            num_frames,
+            pass_frames,
+            repeat_count,
+            fade_out: settings.endless.map(|endless| endless.fade),
+            left: Vec::with_capacity(CHUNK_SIZE),
+            right: Vec::with_capacity(CHUNK_SIZE),
+            tags,
+            tracker: Arc::new(Mutex::new(tracker)),
+            muted: vec![false; channels],
+        }
+    }
+
+
+    /// mutes the channels the player asked for, and tells it where we are
+    fn sync_tracker(&mut self) {
+        let Ok(mut tracker) = self.tracker.lock() else { return };
+        for (channel, (&want, muted)) in tracker.muted.iter().zip(self.muted.iter_mut()).enumerate() {
+            if want != *muted && self.module.set_channel_mute(channel as i32, want) {
+                *muted = want;
+            }
+        }
+        let module = &mut self.module;
+        tracker.order = module.get_current_order();
+        tracker.pattern = module.get_current_pattern();
+        tracker.row = module.get_current_row();
+        tracker.speed = module.get_current_speed();
+        tracker.tempo = module.get_current_tempo();
+        tracker.vu = (0..self.muted.len() as i32)
+            .map(|channel| (module.get_current_channel_vu_left(channel), module.get_current_channel_vu_right(channel)))
+            .collect();
+    }
+}
+
+impl SongDecoder for ModDecoder {
+    fn tags(&self) -> Tags {
+        self.tags.clone()
+    }
+
+    /// one play through, without repeats or loops
+    fn length(&self) -> Duration {
+        Duration::from_secs_f64(self.pass_frames as f64 / self.sample_rate as f64)
+    }
+
+    /// only modules that loop forever fade out
+    fn fade_out(&self) -> Option<Duration> {
+        self.fade_out
+    }
+
+    /// keeps being updated while it plays
+    fn tracker(&self) -> Option<Arc<Mutex<TrackerState>>> {
+        Some(self.tracker.clone())
     }
 }
 
@@ -48,24 +308,41 @@ impl Decoder for ModDecoder {
     type Error = FromFileError;
 
     fn sample_rate(&self) -> u32 {
-        22050
+        self.sample_rate
     }
 
     fn num_frames(&self) -> usize {
-        println!("num_frames called, no# of frames {}",self.frames.len());
-        self.frames.len()
+        self.num_frames
     }
 
-    fn decode(&mut self) -> Result<Vec<kira::dsp::Frame>, Self::Error> {
-        let frames = self.frames.as_slice()[self.pos..self.pos+11025].iter().map(|x| x.clone()).collect();
-        self.pos += 11025;
-        Ok(
-            frames
-        )
+    fn decode(&mut self) -> Result<Vec<Frame>, Self::Error> {
+        self.left.clear();
+        self.right.clear();
+        self.sync_tracker();
+        let rendered = self.module.read_float_stereo(self.sample_rate as i32, &mut self.left, &mut self.right);
+        if rendered == 0 {
+            // libopenmpt says the song is over. but kira keeps asking until it reaches `num_frames`
+            // (which is only a estimate) so we hand it silence instead of a empty chunk it would spin on forever
+            return Ok(vec![Frame::ZERO; CHUNK_SIZE]);
+        }
+        // SAFETY: libopenmpt wrote `rendered` floats into each buffer and never more than their capacity
+        unsafe {
+            self.left.set_len(rendered);
+            self.right.set_len(rendered);
+        }
+        Ok(self.left.iter().zip(self.right.iter()).map(|(&left, &right)| Frame { left, right }).collect())
     }
 
     fn seek(&mut self, index: usize) -> Result<usize, Self::Error> {
-        self.pos = index;
-        Ok(self.pos)
+        // libopenmpt only seeks inside one play through, so work out which one and how many repeats are left after it
+        let mut pass = index / self.pass_frames.max(1);
+        if self.repeat_count >= 0 {
+            pass = pass.min(self.repeat_count as usize);
+            self.module.set_repeat_count(self.repeat_count - pass as i32);
+        }
+        let start = pass * self.pass_frames;
+        let seconds = self.module.set_position_seconds((index - start) as f64 / self.sample_rate as f64);
+        // libopenmpt can only seek to a row so it may land a bit off. kira is fine with earlier but not later
+        Ok((start + (seconds * self.sample_rate as f64).floor() as usize).min(index))
     }
 }
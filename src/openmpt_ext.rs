@@ -0,0 +1,161 @@
+// the bits of libopenmpt that the openmpt crate does not wrap: the extension API's (`libopenmpt_ext.h`) "interactive"
+// interface, which can mute channels while a module plays, and probing a file's header to see if it is a module.
+// libopenmpt is allready linked in by the openmpt crate so we just declare the functions we need.
+//
+// a module made through the extension API is a normal module too, so we still hand out a `openmpt::module::Module`
+// for everything else. that needs a transmute since the crate will not let us build one from a pointer
+
+use std::{
+    ffi::{c_char, c_double, c_int, c_void},
+    fs, io,
+    mem::{size_of, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    path::Path,
+    ptr,
+};
+
+use openmpt::module::Module;
+
+/// `openmpt_module_ext_interface_interactive`. the field order has to match the C struct
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Interactive {
+    set_current_speed: Option<unsafe extern "C" fn(*mut c_void, i32) -> c_int>,
+    set_current_tempo: Option<unsafe extern "C" fn(*mut c_void, i32) -> c_int>,
+    set_tempo_factor: Option<unsafe extern "C" fn(*mut c_void, c_double) -> c_int>,
+    get_tempo_factor: Option<unsafe extern "C" fn(*mut c_void) -> c_double>,
+    set_pitch_factor: Option<unsafe extern "C" fn(*mut c_void, c_double) -> c_int>,
+    get_pitch_factor: Option<unsafe extern "C" fn(*mut c_void) -> c_double>,
+    set_global_volume: Option<unsafe extern "C" fn(*mut c_void, c_double) -> c_int>,
+    get_global_volume: Option<unsafe extern "C" fn(*mut c_void) -> c_double>,
+    set_channel_volume: Option<unsafe extern "C" fn(*mut c_void, i32, c_double) -> c_int>,
+    get_channel_volume: Option<unsafe extern "C" fn(*mut c_void, i32) -> c_double>,
+    set_channel_mute_status: Option<unsafe extern "C" fn(*mut c_void, i32, c_int) -> c_int>,
+    get_channel_mute_status: Option<unsafe extern "C" fn(*mut c_void, i32) -> c_int>,
+    set_instrument_mute_status: Option<unsafe extern "C" fn(*mut c_void, i32, c_int) -> c_int>,
+    get_instrument_mute_status: Option<unsafe extern "C" fn(*mut c_void, i32) -> c_int>,
+    play_note: Option<unsafe extern "C" fn(*mut c_void, i32, i32, c_double, c_double) -> i32>,
+    stop_note: Option<unsafe extern "C" fn(*mut c_void, i32) -> c_int>,
+}
+
+/// `LIBOPENMPT_EXT_C_INTERFACE_INTERACTIVE`
+const INTERACTIVE_ID: &[u8] = b"interactive\0";
+
+extern "C" {
+    fn openmpt_module_ext_create_from_memory(
+        filedata: *const c_void,
+        filesize: usize,
+        logfunc: Option<unsafe extern "C" fn(*const c_char, *mut c_void)>,
+        loguser: *mut c_void,
+        errfunc: Option<unsafe extern "C" fn(c_int, *mut c_void) -> c_int>,
+        erruser: *mut c_void,
+        error: *mut c_int,
+        error_message: *mut *const c_char,
+        ctls: *const c_void,
+    ) -> *mut c_void;
+    fn openmpt_module_ext_destroy(mod_ext: *mut c_void);
+    fn openmpt_module_ext_get_module(mod_ext: *mut c_void) -> *mut c_void;
+    fn openmpt_module_ext_get_interface(mod_ext: *mut c_void, interface_id: *const c_char, interface: *mut c_void, interface_size: usize) -> c_int;
+    fn openmpt_log_func_silent(message: *const c_char, user: *mut c_void);
+    fn openmpt_probe_file_header(
+        flags: u64,
+        data: *const c_void,
+        size: usize,
+        filesize: u64,
+        logfunc: Option<unsafe extern "C" fn(*const c_char, *mut c_void)>,
+        loguser: *mut c_void,
+        errfunc: Option<unsafe extern "C" fn(c_int, *mut c_void) -> c_int>,
+        erruser: *mut c_void,
+        error: *mut c_int,
+        error_message: *mut *const c_char,
+    ) -> c_int;
+}
+
+/// `OPENMPT_PROBE_FILE_HEADER_FLAGS_DEFAULT`: look for modules and for containers of modules
+const PROBE_FLAGS: u64 = 0x1 | 0x2;
+/// `OPENMPT_PROBE_FILE_HEADER_RESULT_SUCCESS`
+const PROBE_SUCCESS: c_int = 1;
+
+/// whether libopenmpt thinks a file that starts with `header` (and is `file_size` long) is a module it can load
+pub fn probe_file_header(header: &[u8], file_size: u64) -> bool {
+    // SAFETY: libopenmpt only reads `header` and does not hold on to it
+    let result = unsafe {
+        openmpt_probe_file_header(
+            PROBE_FLAGS, header.as_ptr() as *const c_void, header.len(), file_size,
+            Some(openmpt_log_func_silent), ptr::null_mut(), None, ptr::null_mut(), ptr::null_mut(), ptr::null_mut(),
+        )
+    };
+    result == PROBE_SUCCESS
+}
+
+// `Module` is just the pointer to the C module. if the crate ever changes that the transmute below is wrong, so check
+const _: () = assert!(size_of::<Module>() == size_of::<*mut c_void>());
+
+/// a module loaded through the extension API. derefs to a normal `Module`
+pub struct ExtModule {
+    ext: *mut c_void,
+    /// the module inside `ext`. never dropped on its own, `ext` owns it
+    module: ManuallyDrop<Module>,
+    /// `None` if this libopenmpt does not have the interactive interface
+    interactive: Option<Interactive>,
+}
+
+impl ExtModule {
+    /// loads a module from a file. `Ok(None)` if the file could be read but libopenmpt could not make sense of it
+    pub fn load(path: &Path) -> io::Result<Option<ExtModule>> {
+        Ok(ExtModule::from_memory(&fs::read(path)?))
+    }
+
+    /// loads a module from the bytes of a module file
+    pub fn from_memory(data: &[u8]) -> Option<ExtModule> {
+        // SAFETY: the data only has to live through the call, libopenmpt copies what it needs
+        let ext = unsafe {
+            openmpt_module_ext_create_from_memory(
+                data.as_ptr() as *const c_void, data.len(),
+                Some(openmpt_log_func_silent), ptr::null_mut(),
+                None, ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), ptr::null(),
+            )
+        };
+        if ext.is_null() {
+            return None;
+        }
+        // SAFETY: `Module` is a single pointer to a `openmpt_module` (checked above) and this is one
+        let module = unsafe { std::mem::transmute::<*mut c_void, Module>(openmpt_module_ext_get_module(ext)) };
+        let mut interactive = std::mem::MaybeUninit::<Interactive>::zeroed();
+        // SAFETY: libopenmpt fills in the struct if it knows the interface, and says so
+        let found = unsafe {
+            openmpt_module_ext_get_interface(ext, INTERACTIVE_ID.as_ptr() as *const c_char, interactive.as_mut_ptr() as *mut c_void, size_of::<Interactive>())
+        };
+        // SAFETY: zeroed is a valid `Interactive` (all the functions are `None`) so this is fine either way
+        let interactive = (found != 0).then(|| unsafe { interactive.assume_init() });
+        Some(ExtModule { ext, module: ManuallyDrop::new(module), interactive })
+    }
+
+    /// mutes or unmutes a channel (from 0). returns false if it could not
+    pub fn set_channel_mute(&mut self, channel: i32, mute: bool) -> bool {
+        let Some(set) = self.interactive.and_then(|i| i.set_channel_mute_status) else { return false };
+        // SAFETY: `ext` is alive as long as we are, and libopenmpt checks the channel number
+        unsafe { set(self.ext, channel, mute as c_int) != 0 }
+    }
+}
+
+impl Deref for ExtModule {
+    type Target = Module;
+
+    fn deref(&self) -> &Module {
+        &self.module
+    }
+}
+
+impl DerefMut for ExtModule {
+    fn deref_mut(&mut self) -> &mut Module {
+        &mut self.module
+    }
+}
+
+impl Drop for ExtModule {
+    fn drop(&mut self) {
+        // SAFETY: this frees the module inside too, which is why `module` is never dropped
+        unsafe { openmpt_module_ext_destroy(self.ext) }
+    }
+}
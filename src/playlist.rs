@@ -0,0 +1,364 @@
+// reading playlist files: (extended) M3U, PLS, XSPF and CUE sheets.
+//
+// playlists can say a bit about their songs (like `#EXTINF` titles). those hints go along with the song in the queue
+// so the player can show something nicer than the file name for songs that have no tags of their own
+
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}, time::Duration};
+
+use crate::{decoder::Region, group, Song};
+
+/// what a playlist told us about a song
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Hint {
+    /// the title the playlist gave it
+    pub title: Option<String>,
+    /// the artist, if the title looked like `Artist - Title`
+    pub artist: Option<String>,
+    /// the album (CUE sheets have one)
+    pub album: Option<String>,
+    /// how long the playlist says it is, in seconds
+    pub duration: Option<f64>,
+}
+
+/// reads any playlist format we know (going by `ext`) into its songs, in order. `None` if it is not a playlist
+pub fn read(playlist: &Path, ext: &str) -> Option<Vec<Song>> {
+    Some(match ext.to_lowercase().as_str() {
+        "m3u" | "m3u8" => read_m3u(playlist),
+        "pls" => read_pls(playlist),
+        "xspf" => read_xspf(playlist),
+        "cue" => read_cue(playlist),
+        _ => return None,
+    })
+}
+
+/// a song from a playlist, with whatever the playlist told us about it
+fn song(path: PathBuf, hint: Option<Hint>) -> Song {
+    Song { hint, ..Song::from(path) }
+}
+
+/// reads a text playlist. `.m3u8` (and anything else that is valid UTF-8) is read as UTF-8, older `.m3u` files as latin-1
+fn read_text(path: &Path) -> String {
+    let bytes = fs::read(path).unwrap_or_default();
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(), // latin-1 maps byte for byte onto unicode
+    };
+    text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text) // drop the BOM some editors add
+}
+
+/// turns a playlist entry into a path. `file://` urls are decoded, relative paths are made relative to the playlist.
+/// `None` for other urls (streams) which we can not play
+fn resolve(entry: &str, playlist: &Path) -> Option<PathBuf> {
+    let path = if let Some(rest) = entry.strip_prefix("file://") {
+        // `file:///music/a.ogg` or `file://localhost/music/a.ogg`
+        let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+        PathBuf::from(percent_decode(rest))
+    } else if entry.contains("://") {
+        println!("skipping {entry:?}: only local files can be played");
+        return None;
+    } else {
+        PathBuf::from(entry)
+    };
+    if path.is_relative() {
+        Some(playlist.parent().unwrap_or(Path::new("")).join(path))
+    } else {
+        Some(path)
+    }
+}
+
+/// decodes `%XX` escapes in a url path
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// parses a `#EXTINF:<seconds>[ attributes],<title>` line
+fn parse_extinf(info: &str) -> Hint {
+    let (head, title) = info.split_once(',').unwrap_or((info, ""));
+    // the seconds can be followed by `key="value"` attributes. -1 means it is not known
+    let duration = head.split_whitespace().next().and_then(|x| x.parse::<f64>().ok()).filter(|&x| x > 0.0);
+    let title = title.trim();
+    let (artist, title) = match title.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim().to_string()), title.trim()),
+        None => (None, title),
+    };
+    Hint { title: (!title.is_empty()).then(|| title.to_string()), artist, duration, ..Default::default() }
+}
+
+/// reads a M3U/M3U8 playlist. `@` lines are parsed into groups (see `group.rs`), everything else is resolved to a path.
+/// VLC's `#EXTVLCOPT:start-time=`/`stop-time=` (in seconds) play only part of the next entry, which is how CUE tracks are saved.
+/// `#EXTSUBSONG:n` picks the subsong (from 0) of the next entry if it is a tracker module
+fn read_m3u(playlist: &Path) -> Vec<Song> {
+    let mut songs = vec![];
+    let mut hint = None;
+    let mut region: Option<Region> = None;
+    let mut subsong = None;
+    for (number, line) in read_text(playlist).lines().enumerate() {
+        let line = line.trim();
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            hint = Some(parse_extinf(info));
+            continue;
+        }
+        if let Some(option) = line.strip_prefix("#EXTVLCOPT:") {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let Some(seconds) = value.trim().parse::<f64>().ok().filter(|&x| x >= 0.0).map(Duration::from_secs_f64) else { continue };
+            let region = region.get_or_insert(Region { start: Duration::ZERO, end: None });
+            match key.trim() {
+                "start-time" => region.start = seconds,
+                "stop-time" => region.end = Some(seconds),
+                _ => {}
+            }
+            continue;
+        }
+        if let Some(n) = line.strip_prefix("#EXTSUBSONG:") {
+            subsong = n.trim().parse().ok().filter(|&n: &i32| n >= -1);
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue; // `#EXTM3U` and the other directives, and plain comments
+        }
+        if line.starts_with('@') {
+            match group::load(line, number + 1, playlist.parent().unwrap_or(Path::new(""))) {
+                Ok(group) => songs.push(group),
+                Err(e) => println!("{}:{e}. skipping the group", playlist.to_string_lossy()),
+            }
+            (hint, region, subsong) = (None, None, None);
+            continue;
+        }
+        if let Some(path) = resolve(line, playlist) {
+            songs.push(Song { region: region.take(), subsong: subsong.take(), ..song(path, hint.take()) });
+        }
+    }
+    songs
+}
+
+/// reads a PLS playlist. it is a ini file with `FileN=`, `TitleN=` and `LengthN=` keys
+fn read_pls(playlist: &Path) -> Vec<Song> {
+    let mut entries: BTreeMap<u32, (Option<String>, Hint)> = BTreeMap::new(); // sorted by N
+    for line in read_text(playlist).lines() {
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let (key, value) = (key.trim().to_lowercase(), value.trim());
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let Ok(n) = key[split..].parse() else { continue };
+        let entry = entries.entry(n).or_default();
+        match &key[..split] {
+            "file" => entry.0 = Some(value.to_string()),
+            "title" => entry.1.title = (!value.is_empty()).then(|| value.to_string()),
+            "length" => entry.1.duration = value.parse().ok().filter(|&x: &f64| x > 0.0),
+            _ => {}
+        }
+    }
+    entries.into_values()
+        .filter_map(|(file, hint)| Some(song(resolve(&file?, playlist)?, Some(hint).filter(|h| *h != Hint::default()))))
+        .collect()
+}
+
+/// the text inside the first `<tag>...</tag>` in `xml`, with entities decoded
+fn xml_text(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    let text = xml[start..end].trim();
+    let text = text.strip_prefix("<![CDATA[").and_then(|t| t.strip_suffix("]]>")).map_or_else(|| xml_unescape(text), str::to_string);
+    (!text.is_empty()).then_some(text)
+}
+
+/// decodes the XML entities (`&amp;`, `&#233;`, etc)
+fn xml_unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        out += &rest[..i];
+        rest = &rest[i..];
+        let Some(end) = rest.find(';') else { break };
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            x if x.starts_with("#x") => u32::from_str_radix(&x[2..], 16).ok().and_then(char::from_u32),
+            x if x.starts_with('#') => x[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out + rest
+}
+
+/// reads a XSPF playlist. there is no XML parser here, but XSPF is simple enough to just look for the tags we want
+fn read_xspf(playlist: &Path) -> Vec<Song> {
+    let text = read_text(playlist);
+    let mut songs = vec![];
+    for track in text.split("<track>").skip(1) {
+        let track = track.split("</track>").next().unwrap_or(track);
+        let Some(location) = xml_text(track, "location") else { continue };
+        // locations are URIs, so relative ones are percent encoded too
+        let location = if location.contains("://") { location } else { percent_decode(&location) };
+        let Some(path) = resolve(&location, playlist) else { continue };
+        let hint = Hint {
+            title: xml_text(track, "title"),
+            artist: xml_text(track, "creator"),
+            album: xml_text(track, "album"),
+            duration: xml_text(track, "duration").and_then(|ms| ms.parse::<f64>().ok()).map(|ms| ms / 1000.0),
+        };
+        songs.push(song(path, Some(hint).filter(|h| *h != Hint::default())));
+    }
+    songs
+}
+
+/// splits a CUE sheet line into its words. `"quoted words"` stay together
+fn cue_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let (word, next) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        words.push(word.to_string());
+        rest = next.trim_start();
+    }
+    words
+}
+
+/// parses a CUE time (`mm:ss:ff`, where there are 75 ff to a second)
+fn cue_time(time: &str) -> Option<Duration> {
+    let mut parts = time.split(':').map(|x| x.parse::<u64>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(Duration::from_secs(m * 60 + s) + Duration::from_secs(f) / 75)
+}
+
+/// reads a CUE sheet. each track becomes its own song that only plays its part of the file
+fn read_cue(playlist: &Path) -> Vec<Song> {
+    let mut songs: Vec<Song> = vec![];
+    let (mut album, mut album_artist) = (None, None);
+    let mut file = None;
+    let mut in_track = false;
+    // the tracks of the file we are in, so the end of each one can be filled in once we see where the next starts
+    let mut file_start = 0;
+    for line in read_text(playlist).lines() {
+        let words = cue_words(line);
+        let (Some(command), Some(value)) = (words.first(), words.get(1)) else { continue };
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                file = resolve(value, playlist);
+                in_track = false;
+                file_start = songs.len();
+            }
+            "TRACK" => {
+                in_track = file.is_some();
+                if let Some(path) = &file {
+                    let hint = Hint { album: album.clone(), artist: album_artist.clone(), ..Default::default() };
+                    songs.push(song(path.clone(), Some(hint)));
+                }
+            }
+            "TITLE" | "PERFORMER" => {
+                let field = match (in_track, songs.last_mut().and_then(|s| s.hint.as_mut())) {
+                    (true, Some(hint)) => if command.eq_ignore_ascii_case("TITLE") { &mut hint.title } else { &mut hint.artist },
+                    _ => if command.eq_ignore_ascii_case("TITLE") { &mut album } else { &mut album_artist },
+                };
+                *field = Some(value.clone());
+            }
+            // INDEX 01 is where the track really starts (INDEX 00 is the gap before it, which belongs to the track before)
+            "INDEX" if in_track && value == "01" => {
+                let Some(start) = words.get(2).and_then(|t| cue_time(t)) else { continue };
+                let index = songs.len() - 1;
+                if index > file_start {
+                    if let Some(region) = songs[index - 1].region.as_mut() {
+                        region.end = Some(start);
+                    }
+                }
+                songs[index].region = Some(Region { start, end: None });
+            }
+            _ => {}
+        }
+    }
+    songs.retain(|song| song.region.is_some()); // tracks with no INDEX 01 can not be played
+    for song in &mut songs {
+        if let (Some(hint), Some(Region { start, end: Some(end) })) = (song.hint.as_mut(), song.region) {
+            hint.duration = Some((end - start).as_secs_f64());
+        }
+    }
+    songs
+}
+
+/// writes `songs` out as a M3U8 playlist that `read_m3u` reads back into the same queue.
+/// songs that were split out of a `@` line are joined back into one, and CUE tracks keep their part of the file
+pub fn write_m3u(playlist: &Path, songs: &[Song]) -> io::Result<()> {
+    fs::write(playlist, m3u_text(songs))
+}
+
+/// the text of the M3U8 playlist `write_m3u` writes
+pub fn m3u_text(songs: &[Song]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    let mut i = 0;
+    while i < songs.len() {
+        // a song and the ones that run straight on from it
+        let group = 1 + songs[i + 1..].iter().take_while(|s| s.grouped).count();
+        let members = match &songs[i].members[..] {
+            [] => &songs[i..i + group],
+            members => members,
+        };
+        i += group;
+        // a `@` line only has paths in it, so a group with CUE tracks or subsongs in it can not be one.
+        // those get written out song by song so at least every song keeps its part of the file
+        if members.len() > 1 && members.iter().all(|s| s.region.is_none() && s.subsong.is_none()) {
+            let words: Vec<String> = members.iter().map(|s| group::quote(&s.path)).collect();
+            out += &format!("@ {}\n", words.join(" "));
+            continue;
+        }
+        for song in members {
+            out += &m3u_entry(song);
+        }
+    }
+    out
+}
+
+/// the lines for a single song in a M3U8 playlist
+fn m3u_entry(song: &Song) -> String {
+    let mut out = String::new();
+    if let Some(hint) = &song.hint {
+        let title = match (&hint.artist, &hint.title) {
+            (Some(artist), Some(title)) => format!("{artist} - {title}"),
+            (None, Some(title)) => title.clone(),
+            _ => String::new(),
+        };
+        out += &format!("#EXTINF:{},{title}\n", hint.duration.map_or(-1, |d| d.round() as i64));
+    }
+    if let Some(region) = song.region {
+        out += &format!("#EXTVLCOPT:start-time={}\n", region.start.as_secs_f64());
+        if let Some(end) = region.end {
+            out += &format!("#EXTVLCOPT:stop-time={}\n", end.as_secs_f64());
+        }
+    }
+    if let Some(subsong) = song.subsong {
+        out += &format!("#EXTSUBSONG:{subsong}\n");
+    }
+    let path = std::path::absolute(&song.path).unwrap_or(song.path.clone()); // so the playlist can be moved around
+    out += &format!("{}\n", path.to_string_lossy());
+    out
+}
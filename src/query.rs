@@ -0,0 +1,391 @@
+// `--query`: picking songs by their tags and file attributes, e.g.
+//
+//   artist:"Toby Fox" AND duration<180 AND format:it
+//
+// the grammar is:
+//
+//   query = and { "OR" and }
+//   and   = not { ["AND"] not }             (two terms next to each other are AND-ed too)
+//   not   = "NOT" not | "(" query ")" | term
+//   term  = field op value | value          (a value on its own looks in the title, artist, album and path)
+//   field = title | artist | album | path | format | duration
+//   op    = ":" (contains, or is for format/duration) | "=" | "!=" | "<" | "<=" | ">" | ">="
+//   value = word | "quoted words"
+//
+// text is matched ignoring case. durations are seconds or `m:ss`
+
+use std::fmt;
+
+use crate::{library::library, Song};
+
+/// what is wrong with a query, and where
+#[derive(Debug)]
+pub struct QueryError {
+    /// the column the problem starts at (in characters, from 1)
+    pub column: usize,
+    /// what is wrong
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+/// what a query can look at for a song
+#[derive(Debug, Default)]
+pub struct Attributes {
+    /// the title tag
+    pub title: Option<String>,
+    /// the artist tag
+    pub artist: Option<String>,
+    /// the album tag
+    pub album: Option<String>,
+    /// the whole path
+    pub path: String,
+    /// the extension, in lower case
+    pub format: String,
+    /// in seconds
+    pub duration: Option<f64>,
+}
+
+/// what a term looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Path,
+    Format,
+    Duration,
+    /// title, artist, album or path
+    Any,
+}
+
+/// how a term compares
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Contains,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+/// a parsed query
+#[derive(Debug)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    /// a single `field op value`
+    Term(Field, Op, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// a quoted value. never a keyword or field
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+/// splits a query into tokens, with the column each one starts at
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        let (token, length) = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '"' => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err(QueryError { column, message: "this quote is never closed".to_string() }),
+                        Some('"') => break,
+                        Some('\\') if j + 1 < chars.len() => {
+                            value.push(chars[j + 1]);
+                            j += 2;
+                        }
+                        Some(&c) => {
+                            value.push(c);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Quoted(value), j + 1 - i)
+            }
+            _ if two == "!=" => (Token::Op(Op::NotEqual), 2),
+            _ if two == "<=" => (Token::Op(Op::LessEqual), 2),
+            _ if two == ">=" => (Token::Op(Op::GreaterEqual), 2),
+            ':' => (Token::Op(Op::Contains), 1),
+            '=' => (Token::Op(Op::Equal), 1),
+            '<' => (Token::Op(Op::Less), 1),
+            '>' => (Token::Op(Op::Greater), 1),
+            // `!=` was taken above, a `!` on its own is not anything
+            '!' => return Err(QueryError { column, message: "unexpected `!` (did you mean `!=` or NOT?)".to_string() }),
+            _ => {
+                // a word runs until whitespace, a bracket, a quote, or a operator (but `3:00` is a word, so `:` only
+                // ends it if what came before is a field name)
+                let mut j = i;
+                while let Some(&c) = chars.get(j) {
+                    let word: String = chars[i..j].iter().collect();
+                    if c.is_whitespace() || "()\"<>=!".contains(c) || (c == ':' && field(&word).is_some()) {
+                        break;
+                    }
+                    j += 1;
+                }
+                (Token::Word(chars[i..j].iter().collect()), j - i)
+            }
+        };
+        tokens.push((token, column));
+        i += length;
+    }
+    Ok(tokens)
+}
+
+/// the field a word names, if it does
+fn field(word: &str) -> Option<Field> {
+    Some(match word.to_lowercase().as_str() {
+        "title" => Field::Title,
+        "artist" => Field::Artist,
+        "album" => Field::Album,
+        "path" => Field::Path,
+        "format" | "ext" => Field::Format,
+        "duration" | "length" => Field::Duration,
+        _ => return None,
+    })
+}
+
+/// parses seconds or `m:ss` (or `h:mm:ss`)
+fn seconds(value: &str) -> Option<f64> {
+    value.split(':').try_fold(0.0, |total, part| Some(total * 60.0 + part.parse::<f64>().ok()?))
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    /// the column just past the end of the query, for errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(_, column)| *column)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, QueryError> {
+        Err(QueryError { column: self.column(), message: message.into() })
+    }
+
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.and()?;
+        while self.keyword("OR") {
+            self.next += 1;
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.not()?;
+        loop {
+            if self.keyword("AND") {
+                self.next += 1;
+            } else if self.peek().is_none() || self.peek() == Some(&Token::Close) || self.keyword("OR") {
+                return Ok(query);
+            }
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Query, QueryError> {
+        if self.keyword("NOT") {
+            self.next += 1;
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            let open = self.column();
+            self.next += 1;
+            let query = self.or()?;
+            if self.peek() != Some(&Token::Close) {
+                return Err(QueryError { column: open, message: "this `(` is never closed".to_string() });
+            }
+            self.next += 1;
+            return Ok(query);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Query, QueryError> {
+        let (field_name, value) = match self.peek().cloned() {
+            Some(Token::Word(word)) => (Some(word), None),
+            Some(Token::Quoted(value)) => (None, Some(value)),
+            Some(_) => return self.error("expected a search term"),
+            None => return self.error("the query ends too soon"),
+        };
+        let column = self.column();
+        self.next += 1;
+        // `field op value`
+        if let (Some(name), Some(Token::Op(op))) = (&field_name, self.peek().cloned()) {
+            let Some(field) = field(name) else {
+                return Err(QueryError { column, message: format!("unknown field `{name}`") });
+            };
+            self.next += 1;
+            let value = match self.peek().cloned() {
+                Some(Token::Word(value) | Token::Quoted(value)) => value,
+                _ => return self.error("expected a value"),
+            };
+            if field == Field::Duration && seconds(&value).is_none() {
+                return self.error(format!("`{value}` is not a length"));
+            }
+            self.next += 1;
+            return Ok(Query::Term(field, op, value));
+        }
+        Ok(Query::Term(Field::Any, Op::Contains, value.or(field_name).unwrap()))
+    }
+}
+
+impl Query {
+    /// parses a query
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, next: 0, end: text.chars().count() + 1 };
+        let query = parser.or()?;
+        if parser.peek().is_some() {
+            return parser.error("unexpected `)`");
+        }
+        Ok(query)
+    }
+
+    /// whether a song with these attributes matches
+    pub fn matches(&self, song: &Attributes) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(song) && b.matches(song),
+            Query::Or(a, b) => a.matches(song) || b.matches(song),
+            Query::Not(a) => !a.matches(song),
+            Query::Term(Field::Any, op, value) => [&song.title, &song.artist, &song.album, &Some(song.path.clone())]
+                .into_iter()
+                .any(|text| compare_text(text.as_deref(), *op, value)),
+            Query::Term(Field::Duration, op, value) => {
+                let (Some(duration), Some(value)) = (song.duration, seconds(value)) else { return false };
+                match op {
+                    Op::Contains | Op::Equal => duration.round() == value.round(),
+                    Op::NotEqual => duration.round() != value.round(),
+                    Op::Less => duration < value,
+                    Op::LessEqual => duration <= value,
+                    Op::Greater => duration > value,
+                    Op::GreaterEqual => duration >= value,
+                }
+            }
+            Query::Term(Field::Format, Op::Contains, value) => song.format.eq_ignore_ascii_case(value.trim_start_matches('.')),
+            Query::Term(field, op, value) => {
+                let text = match field {
+                    Field::Title => song.title.as_deref(),
+                    Field::Artist => song.artist.as_deref(),
+                    Field::Album => song.album.as_deref(),
+                    Field::Path => Some(song.path.as_str()),
+                    _ => Some(song.format.as_str()),
+                };
+                compare_text(text, *op, value)
+            }
+        }
+    }
+}
+
+/// compares a text attribute with a value, ignoring case. a missing attribute only matches `!=`
+fn compare_text(text: Option<&str>, op: Op, value: &str) -> bool {
+    let Some(text) = text else { return op == Op::NotEqual };
+    let (text, value) = (text.to_lowercase(), value.to_lowercase());
+    match op {
+        Op::Contains => text.contains(&value),
+        Op::Equal => text == value,
+        Op::NotEqual => text != value,
+        Op::Less => text < value,
+        Op::LessEqual => text <= value,
+        Op::Greater => text > value,
+        Op::GreaterEqual => text >= value,
+    }
+}
+
+/// the attributes of a song. playlist hints first (they know about CUE tracks), then the library index
+fn attributes(song: &Song) -> Attributes {
+    let mut library = library().lock().unwrap();
+    let entry = library.file(&song.path);
+    let hint = song.hint.clone().unwrap_or_default();
+    Attributes {
+        title: hint.title.or(entry.title),
+        artist: hint.artist.or(entry.artist),
+        album: hint.album.or(entry.album),
+        path: song.path.to_string_lossy().into_owned(),
+        format: entry.format,
+        duration: hint.duration.or(entry.duration),
+    }
+}
+
+/// keeps only the songs that match. a `@` group stays (whole) if any of its songs match
+pub fn filter(songs: &mut Vec<Song>, query: &Query) {
+    songs.retain(|song| {
+        if song.members.is_empty() {
+            query.matches(&attributes(song))
+        } else {
+            song.members.iter().any(|member| query.matches(&attributes(member)))
+        }
+    });
+    if let Err(e) = library().lock().unwrap().save() {
+        println!("could not save the library index: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lone_bang_is_an_error() {
+        let e = Query::parse("!x").unwrap_err();
+        assert_eq!(e.column, 1);
+        let e = Query::parse("title:foo!").unwrap_err();
+        assert_eq!(e.column, 10);
+    }
+
+    #[test]
+    fn not_equal_still_works() {
+        assert!(matches!(Query::parse("title!=foo").unwrap(), Query::Term(Field::Title, Op::NotEqual, _)));
+    }
+
+    #[test]
+    fn unbalanced_parens() {
+        assert_eq!(Query::parse("(title:a OR title:b").unwrap_err().column, 1);
+        assert_eq!(Query::parse("title:a)").unwrap_err().column, 8);
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let e = Query::parse("artist:\"Toby Fox").unwrap_err();
+        assert_eq!(e.column, 8);
+    }
+}
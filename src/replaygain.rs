@@ -0,0 +1,172 @@
+// replaygain / loudness normalization. evens out the volume between loud modern masters, quiet old mp3s and tracker music.
+// songs without replaygain tags can have their loudness measured (`--analyze-loudness`). that means decoding all of the
+// song, so it is done on a thread of its own a few songs ahead, and kept in the library index for next time
+
+use std::{collections::BTreeSet, f64::consts::PI, path::PathBuf, sync::{mpsc, Mutex, OnceLock}, thread};
+
+use clap::ValueEnum;
+use kira::sound::streaming::Decoder;
+
+use crate::{decoder::TrackDecoder, library::library, tags::Tags, Song};
+
+/// the loudness (in LUFS) that replaygain 2.0 normalizes everything to
+const REFERENCE_LUFS: f64 = -18.0;
+
+/// which replaygain value to use
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ReplayGainMode {
+    /// make every track the same loudness
+    Track,
+    /// keep the loudness differences within a album but even albums out against each other
+    Album,
+    /// play everything as is
+    Off,
+}
+
+/// works out the gain (in dB) to play a song at. falls back to `loudness` (measured in LUFS) when the song has no tags.
+/// the gain is lowered if it would make the song's peak clip
+pub fn gain_db(tags: &Tags, mode: ReplayGainMode, loudness: Option<f64>) -> f64 {
+    let (gain, peak) = match mode {
+        ReplayGainMode::Off => return 0.0,
+        // album mode falls back to the track values if there are no album tags
+        ReplayGainMode::Album => (tags.album_gain.or(tags.track_gain), tags.album_peak.or(tags.track_peak)),
+        ReplayGainMode::Track => (tags.track_gain.or(tags.album_gain), tags.track_peak.or(tags.album_peak)),
+    };
+    let Some(gain) = gain.or(loudness.map(|l| REFERENCE_LUFS - l)) else { return 0.0 };
+    match peak {
+        Some(peak) if peak > 0.0 => gain.min(-20.0 * peak.log10()),
+        _ => gain,
+    }
+}
+
+/// a biquad filter. two of these make up the EBU R128 "K-weighting" filter
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    /// the last two inputs and outputs for each channel
+    state: [[f64; 4]; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Biquad {
+        Biquad { b, a, state: [[0.0; 4]; 2] }
+    }
+
+    fn process(&mut self, channel: usize, x: f64) -> f64 {
+        let [x1, x2, y1, y2] = self.state[channel];
+        let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[1] * y1 - self.a[2] * y2;
+        self.state[channel] = [x, x1, y, y1];
+        y
+    }
+}
+
+/// measures the integrated loudness (EBU R128, in LUFS) of a whole song by decoding all of it.
+/// returns `None` if the song is silent
+pub fn measure_loudness(decoder: &mut impl Decoder) -> Option<f64> {
+    let rate = decoder.sample_rate() as f64;
+    // the K-weighting filter (a high shelf then a high pass) from ITU-R BS.1770, worked out for our sample rate
+    let k = (PI * 1681.974450955533 / rate).tan();
+    let (q, vh) = (0.7071752369554196, 10f64.powf(3.999843853973347 / 20.0));
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let mut shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    let k = (PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let mut high_pass = Biquad::new([1.0, -2.0, 1.0], [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+    // mean square power of every 100ms. 4 of them in a row make up a 400ms gating block
+    let segment_len = (rate / 10.0).round() as usize;
+    let mut segments = vec![];
+    let (mut sum, mut count, mut decoded) = (0.0, 0, 0);
+    while decoded < decoder.num_frames() {
+        let frames = decoder.decode().ok()?;
+        if frames.is_empty() {
+            break;
+        }
+        decoded += frames.len();
+        for frame in frames {
+            for (channel, x) in [frame.left, frame.right].into_iter().enumerate() {
+                let y = high_pass.process(channel, shelf.process(channel, x as f64));
+                sum += y * y;
+            }
+            count += 1;
+            if count == segment_len {
+                segments.push(sum / count as f64);
+                (sum, count) = (0.0, 0);
+            }
+        }
+    }
+    let blocks: Vec<f64> = segments.windows(4).map(|w| w.iter().sum::<f64>() / 4.0).collect();
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let mean = |blocks: &[f64]| if blocks.is_empty() { None } else { Some(blocks.iter().sum::<f64>() / blocks.len() as f64) };
+    // first throw out anything quieter than -70 LUFS, then anything 10 LU quieter than what is left
+    let gated: Vec<f64> = blocks.into_iter().filter(|&b| loudness(b) > -70.0).collect();
+    let relative = loudness(mean(&gated)?) - 10.0;
+    let gated: Vec<f64> = gated.into_iter().filter(|&b| loudness(b) > relative).collect();
+    Some(loudness(mean(&gated)?))
+}
+
+/// what part of its file a song is, for keeping its loudness in the library index. empty for the whole file,
+/// `<start>-<end>` (in seconds) for a CUE track, and `#<n>` on the end for a subsong of a tracker module
+pub fn part(song: &Song) -> String {
+    let mut part = String::new();
+    if let Some(region) = song.region {
+        part += &format!("{}-{}", region.start.as_secs_f64(), region.end.map_or(String::new(), |end| end.as_secs_f64().to_string()));
+    }
+    if let Some(subsong) = song.subsong {
+        part += &format!("#{subsong}");
+    }
+    part
+}
+
+/// the songs handed to the measuring thread this run, so none get measured twice
+static REQUESTED: Mutex<BTreeSet<(PathBuf, String)>> = Mutex::new(BTreeSet::new());
+/// where to send songs to be measured. the thread is started the first time
+static MEASURER: OnceLock<Mutex<mpsc::Sender<Song>>> = OnceLock::new();
+
+/// the measured loudness (in LUFS) of a song, if it is known yet. if not it gets measured in the background
+pub fn loudness(song: &Song) -> Option<f64> {
+    let known = library().lock().unwrap().loudness(&song.path, &part(song));
+    if known.is_none() {
+        request(song);
+    }
+    known.flatten()
+}
+
+/// has a song measured in the background, unless it allready was (or is being)
+pub fn request(song: &Song) {
+    let key = (song.path.clone(), part(song));
+    if !REQUESTED.lock().unwrap().insert(key.clone()) {
+        return;
+    }
+    if library().lock().unwrap().loudness(&key.0, &key.1).is_some() {
+        return;
+    }
+    let sender = MEASURER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || measure_songs(receiver));
+        Mutex::new(sender)
+    });
+    let _ = sender.lock().unwrap().send(song.clone());
+}
+
+/// measures songs as they come in and puts them in the library index. runs on its own thread
+fn measure_songs(songs: mpsc::Receiver<Song>) {
+    for song in songs {
+        // just the part that plays, a CUE track is not as loud as the whole album
+        let lufs = match TrackDecoder::open_part(&song.path, song.region, song.subsong) {
+            Ok(decoder) if decoder.tags().track_gain.is_some() || decoder.tags().album_gain.is_some() => continue, // tagged
+            Ok(mut decoder) => measure_loudness(&mut decoder),
+            Err(_) => None,
+        };
+        let mut library = library().lock().unwrap();
+        library.set_loudness(&song.path, &part(&song), lufs);
+        if let Err(e) = library.save() {
+            println!("could not save the library index: {e}");
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// saves the queue, the history and how far into the current song we are, so `--resume` can carry on where we left off.
+// it is all M3U8 (see `playlist.rs`) under the state folder, so it can be looked at (or fixed) by hand.
+// there is also a longer log of what played, so `--no-repeat` shuffles can stay away from it
+
+use std::{fs, io, path::{Path, PathBuf}};
+
+use kira::sound::PlaybackState;
+
+use crate::{dirs::state_dir, playlist, Song, Status};
+
+/// the current song then the queue
+const QUEUE_FILE: &str = "session.m3u8";
+/// the songs played before the current one, newest first
+const HISTORY_FILE: &str = "history.m3u8";
+/// every song that played, oldest first. one path per line
+const PLAYS_FILE: &str = "plays.log";
+/// how many songs the play log keeps
+const PLAYS_LENGTH: usize = 1000;
+
+/// the line in the queue file that says how far into the first song we were (in seconds)
+const POSITION_PREFIX: &str = "#POSITION:";
+
+/// a saved session
+pub struct Session {
+    /// the songs to play, starting with the one that was playing
+    pub upcoming: Vec<Song>,
+    /// the history, newest first
+    pub lookback: Vec<Song>,
+    /// how far into the first song of `upcoming` we were
+    pub position: f64,
+}
+
+fn path(file: &str) -> PathBuf {
+    state_dir().join(file)
+}
+
+/// saves the session. called every so often and on quit
+pub fn save(state: &Status) -> io::Result<()> {
+    fs::create_dir_all(state_dir())?;
+    // the current song only counts if it is still going. otherwise it has played and is just history
+    let playing = state.handle.as_ref().is_some_and(|h| h.state() != PlaybackState::Stopped);
+    let mut queue = state.queued_songs();
+    let mut position = 0.0;
+    if playing {
+        position = state.handle.as_ref().map_or(0.0, |h| h.position());
+    } else if !state.lookback.is_empty() {
+        queue.remove(0);
+    }
+    let history: Vec<Song> = state.lookback.iter().skip(playing as usize)
+        .map(|song| Song { grouped: false, ..song.clone() }) // it is backwards, so the groups would not line up anyway
+        .collect();
+    fs::write(path(QUEUE_FILE), format!("{}{POSITION_PREFIX}{position}\n", playlist::m3u_text(&queue)))?;
+    fs::write(path(HISTORY_FILE), playlist::m3u_text(&history))
+}
+
+/// loads the saved session, if there is one
+pub fn load() -> Option<Session> {
+    let text = fs::read_to_string(path(QUEUE_FILE)).ok()?;
+    let position = text.lines().find_map(|line| line.strip_prefix(POSITION_PREFIX)?.trim().parse().ok()).unwrap_or(0.0);
+    Some(Session {
+        upcoming: playlist::read(&path(QUEUE_FILE), "m3u8")?,
+        lookback: playlist::read(&path(HISTORY_FILE), "m3u8").unwrap_or_default(),
+        position,
+    })
+}
+
+/// adds a song to the play log
+pub fn record_play(song: &Path) -> io::Result<()> {
+    fs::create_dir_all(state_dir())?;
+    let text = fs::read_to_string(path(PLAYS_FILE)).unwrap_or_default();
+    let mut plays: Vec<&str> = text.lines().collect();
+    let song = song.to_string_lossy();
+    plays.push(&song);
+    let keep = plays.len().saturating_sub(PLAYS_LENGTH);
+    fs::write(path(PLAYS_FILE), plays[keep..].join("\n") + "\n")
+}
+
+/// the songs in the play log, newest first
+pub fn plays() -> Vec<PathBuf> {
+    let text = fs::read_to_string(path(PLAYS_FILE)).unwrap_or_default();
+    text.lines().rev().map(PathBuf::from).collect()
+}
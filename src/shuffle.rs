@@ -0,0 +1,97 @@
+// shuffling the queue. what gets shuffled as one unit depends on the mode
+
+use std::{collections::{HashMap, VecDeque}, path::PathBuf};
+
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, Rng};
+
+use crate::Song;
+
+/// what to keep together when shuffling
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ShuffleMode {
+    /// every song on its own, even the ones in `@` groups
+    Track,
+    /// whole albums (by album tag, or else by folder), keeping the songs of each album in order
+    Album,
+    /// every song on its own, but `@` groups stay together
+    Group,
+}
+
+/// how many of the last picked songs can not share a artist (or folder) with the next one, if we can help it
+const SPREAD: usize = 3;
+/// how far down the list to look for a song that is not by the same artist. keeps big libraries quick
+const SPREAD_LOOKAHEAD: usize = 64;
+
+/// what album a song is on. the playlist's album (CUE sheets have one) if it has one, otherwise the folder it is in
+fn album_of(song: &Song) -> (Option<String>, PathBuf) {
+    let song = song.members.first().unwrap_or(song); // a group goes with its first song
+    let album = song.hint.as_ref().and_then(|hint| hint.album.clone());
+    (album.clone(), if album.is_some() { PathBuf::new() } else { song.path.parent().map(PathBuf::from).unwrap_or_default() })
+}
+
+/// shuffles `songs` (in play order) according to `mode`
+pub fn shuffle(songs: &mut Vec<Song>, mode: ShuffleMode, rng: &mut impl Rng) {
+    match mode {
+        ShuffleMode::Group => songs.shuffle(rng),
+        ShuffleMode::Track => {
+            // break the groups up into their songs first
+            let mut tracks: Vec<Song> = songs.drain(..)
+                .flat_map(|song| if song.members.is_empty() { vec![song] } else { song.members })
+                .map(|song| Song { grouped: false, ..song })
+                .collect();
+            tracks.shuffle(rng);
+            *songs = tracks;
+        }
+        ShuffleMode::Album => {
+            // collect the albums in the order they first show up, then shuffle the albums
+            let mut albums: Vec<Vec<Song>> = vec![];
+            let mut index = HashMap::new();
+            for song in songs.drain(..) {
+                let i = *index.entry(album_of(&song)).or_insert_with(|| {
+                    albums.push(vec![]);
+                    albums.len() - 1
+                });
+                albums[i].push(song);
+            }
+            albums.shuffle(rng);
+            *songs = albums.into_iter().flatten().collect();
+        }
+    }
+}
+
+/// who made a song, for spreading them out. the playlist's artist, or else the folder the song is in
+fn artist_of(song: &Song) -> String {
+    let song = song.members.first().unwrap_or(song);
+    match song.hint.as_ref().and_then(|hint| hint.artist.clone()) {
+        Some(artist) => artist.to_lowercase(),
+        None => song.path.parent().map_or(String::new(), |folder| folder.to_string_lossy().into_owned()),
+    }
+}
+
+/// reorders allready shuffled `songs` so recently played ones (`recent`, newest first) come late, and songs by the
+/// same artist (or from the same folder) do not end up next to each other
+pub fn spread(songs: &mut Vec<Song>, recent: &[PathBuf], rng: &mut impl Rng) {
+    let rank: HashMap<&PathBuf, usize> = recent.iter().enumerate().rev().map(|(i, path)| (path, i)).collect();
+    // weighted shuffle (each song gets `ln(random) / weight` and the biggest go first). songs that have not played
+    // lately have a weight of 1, the rest get less the more recent they were
+    let mut keyed: Vec<(f64, Song)> = songs.drain(..).map(|song| {
+        let played = song.members.iter().chain([&song]).filter_map(|s| rank.get(&s.path)).min();
+        let weight = played.map_or(1.0, |&r| 0.01 * (r + 1) as f64 / (recent.len() + 1) as f64);
+        (rng.gen::<f64>().ln() / weight, song)
+    }).collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    // then take them in that order, skipping ahead a little when the next one is by a artist we just had
+    let mut left: VecDeque<Song> = keyed.into_iter().map(|(_, song)| song).collect();
+    let mut last: VecDeque<String> = VecDeque::with_capacity(SPREAD);
+    while !left.is_empty() {
+        let pick = left.iter().take(SPREAD_LOOKAHEAD).position(|song| !last.contains(&artist_of(song))).unwrap_or(0);
+        let song = left.remove(pick).unwrap();
+        if last.len() == SPREAD {
+            last.pop_front();
+        }
+        last.push_back(artist_of(&song));
+        songs.push(song);
+    }
+}
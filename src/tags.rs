@@ -0,0 +1,88 @@
+// pulls the tags we care about out of whatever metadata symphonia found in a file.
+
+use symphonia::core::meta::{MetadataRevision, StandardTagKey, Tag};
+
+use crate::cover::{embedded_cover, Picture};
+
+/// the tags of a song that the player uses
+#[derive(Debug, Default, Clone)]
+pub struct Tags {
+    /// the title of the song
+    pub title: Option<String>,
+    /// who made the song
+    pub artist: Option<String>,
+    /// the album the song is on
+    pub album: Option<String>,
+    /// a comment (or for tracker music, the song message)
+    pub comment: Option<String>,
+    /// the cover art embedded in the song
+    pub cover: Option<Picture>,
+    /// a `file://` url to the song's cover art. only filled in once the song plays (see `Status::tags_for`)
+    pub cover_url: Option<String>,
+    /// replaygain for just this track (in dB)
+    pub track_gain: Option<f64>,
+    /// the loudest sample in this track (1.0 is full scale)
+    pub track_peak: Option<f64>,
+    /// replaygain for the whole album the track is on (in dB)
+    pub album_gain: Option<f64>,
+    /// the loudest sample on the whole album
+    pub album_peak: Option<f64>,
+}
+
+impl Tags {
+    /// fills in any tags we do not have yet from a metadata revision. earlier revisions win
+    pub fn merge(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let text = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::Comment) => &mut self.comment,
+                _ => {
+                    self.merge_number(tag);
+                    continue;
+                }
+            };
+            if text.is_none() {
+                *text = non_empty(tag.value.to_string());
+            }
+        }
+        if self.cover.is_none() {
+            self.cover = embedded_cover(revision.visuals());
+        }
+        // some files only have a album artist
+        if self.artist.is_none() {
+            self.artist = revision.tags().iter()
+                .find(|tag| tag.std_key == Some(StandardTagKey::AlbumArtist))
+                .and_then(|tag| non_empty(tag.value.to_string()));
+        }
+    }
+
+    /// fills in a replaygain tag if it is one and we do not have it yet
+    fn merge_number(&mut self, tag: &Tag) {
+        // symphonia only knows the upper case names in ID3 `TXXX` frames, but some taggers write them in lower case.
+        // so if it did not recognise the tag we look at the name ourselves
+        let name = tag.key.strip_prefix("TXXX:").unwrap_or(&tag.key).to_ascii_uppercase();
+        let field = match (tag.std_key, name.as_str()) {
+            (Some(StandardTagKey::ReplayGainTrackGain), _) | (None, "REPLAYGAIN_TRACK_GAIN") => &mut self.track_gain,
+            (Some(StandardTagKey::ReplayGainTrackPeak), _) | (None, "REPLAYGAIN_TRACK_PEAK") => &mut self.track_peak,
+            (Some(StandardTagKey::ReplayGainAlbumGain), _) | (None, "REPLAYGAIN_ALBUM_GAIN") => &mut self.album_gain,
+            (Some(StandardTagKey::ReplayGainAlbumPeak), _) | (None, "REPLAYGAIN_ALBUM_PEAK") => &mut self.album_peak,
+            _ => return,
+        };
+        if field.is_none() {
+            *field = parse_number(&tag.value.to_string());
+        }
+    }
+}
+
+/// trims a tag value. `None` if there is nothing left
+pub fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() { None } else { Some(value.to_string()) }
+}
+
+/// parses the number at the start of a tag value. replaygain tags look like `-6.48 dB` so we ignore the unit
+fn parse_number(value: &str) -> Option<f64> {
+    value.split_whitespace().next()?.parse().ok()
+}
@@ -0,0 +1,148 @@
+// a small terminal UI. shows what is playing, the queue and the history, and takes single key commands.
+// there are no terminal crates here so it is just `stty` (to read keys without waiting for enter) and ANSI escape codes
+
+use std::{io::{self, Read, Write}, process::{Command, Stdio}, sync::OnceLock, thread};
+
+use souvlaki::{MediaControlEvent, SeekDirection};
+
+use crate::{playlist::Hint, Song, Status, GLOBAL_STATE};
+
+/// how many songs of the queue and of the history to show
+const LIST_LENGTH: usize = 10;
+/// how wide the progress bar is
+const BAR_WIDTH: usize = 40;
+/// how wide a channel's VU meter is
+const VU_WIDTH: usize = 20;
+/// how many channels of a tracker module to show
+const MAX_CHANNELS: usize = 32;
+/// how much one press of `+`/`-` changes the volume by
+const VOLUME_STEP: f64 = 0.05;
+
+/// the terminal settings from before we started, so we can put them back on exit
+static SAVED_TTY: OnceLock<String> = OnceLock::new();
+
+/// runs `stty` on our terminal. returns what it printed if it worked
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// switches the terminal to reading single keys and starts listening for them
+pub fn start() {
+    if let Some(saved) = stty(&["-g"]) {
+        let _ = SAVED_TTY.set(saved);
+    }
+    // keys come in as soon as they are pressed, and are not printed. ctrl-c comes in as a key too (instead of killing us
+    // on the spot) so it can quit properly and put the terminal back
+    stty(&["-icanon", "-echo", "-isig"]);
+    print!("\x1b[?25l"); // hide the cursor
+    thread::spawn(read_keys);
+}
+
+/// puts the terminal back how it was before `start`. does nothing if the UI was never started
+pub fn restore() {
+    if let Some(saved) = SAVED_TTY.get() {
+        stty(&[saved]);
+        println!("\x1b[?25h"); // show the cursor again
+    }
+}
+
+/// reads keys forever and turns them into commands. runs on its own thread
+fn read_keys() {
+    let mut bytes = io::stdin().lock().bytes().map_while(Result::ok);
+    while let Some(byte) = bytes.next() {
+        let mut state = GLOBAL_STATE.get().unwrap().lock().unwrap();
+        match byte {
+            b' ' => state.handle_event(MediaControlEvent::Toggle),
+            b'n' => state.handle_event(MediaControlEvent::Next),
+            b'p' => state.handle_event(MediaControlEvent::Previous),
+            b'q' | 0x03 => state.handle_event(MediaControlEvent::Quit), // 0x03 is ctrl-c
+            b'+' | b'=' => { let volume = state.volume + VOLUME_STEP; state.set_volume(volume) },
+            b'-' => { let volume = state.volume - VOLUME_STEP; state.set_volume(volume) },
+            b's' => state.shuffle_upcoming(),
+            // tracker modules: 1-9 mute a channel, 0 unmutes them all
+            b'1'..=b'9' => { let _ = state.toggle_channel((byte - b'0') as usize, false); },
+            b'0' => state.unmute_channels(),
+            // arrow keys come in as `ESC [ C` (right) and `ESC [ D` (left)
+            0x1b => {
+                drop(state); // dont hold the lock while waiting for the rest of the key
+                if bytes.next() != Some(b'[') { continue }
+                let direction = match bytes.next() {
+                    Some(b'C') => SeekDirection::Forward,
+                    Some(b'D') => SeekDirection::Backward,
+                    _ => continue,
+                };
+                GLOBAL_STATE.get().unwrap().lock().unwrap().handle_event(MediaControlEvent::Seek(direction));
+                continue;
+            }
+            _ => continue,
+        }
+        draw(&state); // redraw right away so the key feels responsive
+    }
+}
+
+/// formats seconds as `m:ss`
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// the name to show for a song in the lists
+fn song_name(song: &Song) -> String {
+    if song.path.to_string_lossy().starts_with('@') {
+        return song.path.to_string_lossy().into_owned(); // show `@` lines as is
+    }
+    if let Some(Hint { title: Some(title), artist, duration, .. }) = song.hint.clone() {
+        let artist = artist.map_or(String::new(), |a| format!("{a} - "));
+        let duration = duration.map_or(String::new(), |d| format!(" ({})", format_time(d)));
+        return format!("{artist}{title}{duration}");
+    }
+    song.path.file_name().map_or_else(|| song.path.to_string_lossy(), |x| x.to_string_lossy()).into_owned()
+}
+
+/// draws the whole UI
+pub fn draw(state: &Status) {
+    let mut out = String::from("\x1b[H\x1b[2J"); // move to the top left and clear the screen
+    let current = state.lookback.front();
+    let title = state.tags.title.clone().or(current.map(song_name)).unwrap_or_else(|| "nothing".to_string());
+    out += &format!(" {} {title}\n", if state.paused { "||" } else { "|>" });
+    if let Some(artist) = &state.tags.artist {
+        out += &format!("    by {artist}\n");
+    }
+    if let Some(album) = &state.tags.album {
+        out += &format!("    on {album}\n");
+    }
+    let position = state.handle.as_ref().map_or(0.0, |h| h.position());
+    let total = state.duration.as_secs_f64();
+    let filled = if total > 0.0 { ((position / total) * BAR_WIDTH as f64) as usize } else { 0 }.min(BAR_WIDTH);
+    out += &format!(
+        "\n [{}{}] {} / {}   vol {:.0}%\n",
+        "#".repeat(filled), "-".repeat(BAR_WIDTH - filled),
+        format_time(position), format_time(total), state.volume * 100.0,
+    );
+
+    if let Some(tracker) = &state.tracker {
+        let tracker = tracker.lock().unwrap();
+        out += &format!(
+            "\n order {:3}/{}  pattern {:3}  row {:3}  speed {:2}  tempo {:3}\n",
+            tracker.order, tracker.num_orders, tracker.pattern, tracker.row, tracker.speed, tracker.tempo,
+        );
+        for (i, (&(left, right), &muted)) in tracker.vu.iter().zip(tracker.muted.iter()).enumerate().take(MAX_CHANNELS) {
+            let level = ((left.max(right).clamp(0.0, 1.0) * VU_WIDTH as f32) as usize).min(VU_WIDTH);
+            let meter = if muted { format!("{:-<VU_WIDTH$}", " muted ") } else { format!("{}{}", "|".repeat(level), " ".repeat(VU_WIDTH - level)) };
+            out += &format!("  {:2} [{meter}]\n", i + 1);
+        }
+    }
+
+    out += "\n up next:\n";
+    for (i, song) in state.preloaded.iter().map(|p| &p.song).chain(state.upcoming.iter()).take(LIST_LENGTH).enumerate() {
+        out += &format!("  {:2}. {}\n", i + 1, song_name(song));
+    }
+    out += "\n history:\n";
+    for song in state.lookback.iter().skip(1).take(LIST_LENGTH) { // skip the current song
+        out += &format!("      {}\n", song_name(song));
+    }
+    out += "\n space pause  n next  p previous  <-/-> seek  +/- volume  s shuffle  1-9/0 mute/unmute channels  q quit\n";
+    print!("{out}");
+    let _ = io::stdout().flush();
+}